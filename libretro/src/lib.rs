@@ -0,0 +1,520 @@
+//! The chiprs emulator as a libretro core, for RetroArch and other libretro frontends.
+//!
+//! Video is XRGB8888 at the program's current resolution, 64x32 or 128x64.
+//! Audio is a square wave, or the XO-CHIP audio pattern, while the sound timer is running.
+//! The 16-key pad is mapped to the keyboard the same way as the chiprs binary does it,
+//! and to the RetroPad as listed in [`JOYPAD_KEYS`].
+//!
+//! Programs ending in `.sc8` run with SUPER-CHIP quirks, `.xo8` with XO-CHIP quirks,
+//! and everything else with the binary's default quirks. Octo source files (`.8o`) are assembled on load.
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::sync::Mutex;
+
+use chiprs::assembler::assemble;
+use chiprs::{Chip8, Engine, Quirks, TIMER_PERIOD};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+/// The timers' rate, so that each frame runs the emulator for one [`TIMER_PERIOD`]
+const FRAMES_PER_SECOND: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44_100.0;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+/// Frequency of the plain beep, for programs without an XO-CHIP audio pattern
+const BEEP_FREQUENCY: f32 = 440.0;
+const VOLUME: i16 = 4000;
+/// Room for the stack to grow between a call to `retro_serialize_size` and `retro_serialize`
+const SERIALIZE_HEADROOM: usize = 256;
+
+/// Colors for each combination of the two XO-CHIP planes, the same as the native frontend's
+const PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xFF6600, 0x662200];
+
+/// RetroPad button IDs and the CHIP-8 keys they press. The d-pad is 2/4/6/8 and A is 5,
+/// which is what most programs use for movement and action.
+pub const JOYPAD_KEYS: [(c_uint, usize); 16] = [
+    (4, 0x2),  // up
+    (5, 0x8),  // down
+    (6, 0x4),  // left
+    (7, 0x6),  // right
+    (8, 0x5),  // A
+    (0, 0x0),  // B
+    (9, 0x1),  // X
+    (1, 0x3),  // Y
+    (10, 0x7), // L
+    (11, 0x9), // R
+    (12, 0xA), // L2
+    (13, 0xB), // R2
+    (2, 0xC),  // select
+    (14, 0xD), // L3
+    (15, 0xE), // R3
+    (3, 0xF),  // start
+];
+
+/// Keyboard keys and the CHIP-8 keys they press. libretro key codes for digits and letters are ASCII.
+const KEYBOARD_KEYS: [(u8, usize); 16] = [
+    (b'1', 0x1),
+    (b'2', 0x2),
+    (b'3', 0x3),
+    (b'4', 0xC),
+    (b'q', 0x4),
+    (b'w', 0x5),
+    (b'e', 0x6),
+    (b'r', 0xD),
+    (b'a', 0x7),
+    (b's', 0x8),
+    (b'd', 0x9),
+    (b'f', 0xE),
+    (b'z', 0xA),
+    (b'x', 0x0),
+    (b'c', 0xB),
+    (b'v', 0xF),
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// The functions the frontend hands to the core
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+/// A loaded program
+struct Game {
+    emulator: Chip8,
+    /// kept for `retro_reset`
+    program: Vec<u8>,
+    quirks: Quirks,
+    /// the program stops running after an error, rather than taking down the frontend
+    crashed: bool,
+    /// XRGB8888 pixels of the display
+    frame: Vec<u32>,
+    /// interleaved stereo samples for one frame
+    audio: Vec<i16>,
+    /// position within the waveform, from 0 to 1
+    audio_phase: f32,
+}
+
+// SAFETY: Chip8 is only !Send because of what it can be given to call or share:
+// a custom random number generator, a trace hook, a 0NNN handler, or the `Rc` blocks of
+// `Engine::Recompiler`. `Game::new` is the only place an emulator is made, and it keeps
+// load_program's defaults of the interpreter with 0NNN trapping, with nothing installed.
+unsafe impl Send for Game {}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static GAME: Mutex<Option<Game>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+impl Game {
+    fn new(program: Vec<u8>, quirks: Quirks) -> Option<Self> {
+        let emulator = Chip8::load_program(&program, quirks).ok()?;
+        // see the SAFETY comment on Game
+        assert_eq!(emulator.engine(), Engine::Interpreter);
+        Some(Game {
+            emulator,
+            program,
+            quirks,
+            crashed: false,
+            frame: Vec::new(),
+            audio: Vec::with_capacity(SAMPLES_PER_FRAME * 2),
+            audio_phase: 0.0,
+        })
+    }
+
+    fn run_frame(&mut self, pressed_keys: [bool; 16]) {
+        if self.crashed {
+            // let the sound run out
+            self.emulator.tick_timers();
+            return;
+        }
+        if let Err(err) = self.emulator.run_for(TIMER_PERIOD, pressed_keys) {
+            eprintln!("chiprs: {err}");
+            self.crashed = true;
+        }
+    }
+
+    fn render(&mut self) {
+        let display = &self.emulator.display;
+        self.frame.clear();
+        for row in display.rows() {
+            self.frame
+                .extend(row.iter().map(|&color| PALETTE[color as usize]));
+        }
+    }
+
+    /// Fills `audio` with one frame of samples
+    fn generate_audio(&mut self) {
+        self.audio.clear();
+        if !self.emulator.is_sound_on() {
+            self.audio_phase = 0.0;
+            self.audio.resize(SAMPLES_PER_FRAME * 2, 0);
+            return;
+        }
+        let pattern = self.emulator.audio_pattern();
+        // the phase covers one period of the beep, or all 128 samples of the pattern
+        let phase_inc = match pattern {
+            Some(pattern) => pattern.sample_rate() / 128.0 / SAMPLE_RATE as f32,
+            None => BEEP_FREQUENCY / SAMPLE_RATE as f32,
+        };
+        for _ in 0..SAMPLES_PER_FRAME {
+            let high = match pattern {
+                Some(pattern) => {
+                    let bit = (self.audio_phase * 128.0) as usize % 128;
+                    pattern.buffer[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.audio_phase < 0.5,
+            };
+            let sample = if high { VOLUME } else { -VOLUME };
+            self.audio.extend([sample, sample]);
+            self.audio_phase = (self.audio_phase + phase_inc) % 1.0;
+        }
+    }
+}
+
+/// Reads the 16-key pad from the keyboard and the first RetroPad
+fn pressed_keys(input_state: InputStateFn) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (button, key) in JOYPAD_KEYS {
+        // SAFETY: the frontend's callback, called as the libretro API specifies
+        keys[key] |= unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, button) } != 0;
+    }
+    for (code, key) in KEYBOARD_KEYS {
+        // SAFETY: as above
+        keys[key] |= unsafe { input_state(0, RETRO_DEVICE_KEYBOARD, 0, code as c_uint) } != 0;
+    }
+    keys
+}
+
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+}
+
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+/// Audio is sent a frame at a time through the batch callback instead.
+///
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+/// # Safety
+/// Called by the libretro frontend.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *GAME.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    info.write(RetroSystemInfo {
+        library_name: c"chiprs".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+        valid_extensions: c"ch8|sc8|xo8|8o".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    });
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    info.write(RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: chiprs::LORES_WIDTH as c_uint,
+            base_height: chiprs::LORES_HEIGHT as c_uint,
+            max_width: chiprs::HIRES_WIDTH as c_uint,
+            max_height: chiprs::HIRES_HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAMES_PER_SECOND,
+            sample_rate: SAMPLE_RATE,
+        },
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(game) = GAME.lock().unwrap().as_mut() {
+        if let Some(reset) = Game::new(game.program.clone(), game.quirks) {
+            *game = reset;
+        }
+    }
+}
+
+/// Runs one 60 Hz frame: polls input, executes instructions, ticks the timers,
+/// and sends the frame's video and audio to the frontend.
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut game = GAME.lock().unwrap();
+    let Some(game) = game.as_mut() else {
+        return;
+    };
+    let mut keys = [false; 16];
+    if let (Some(input_poll), Some(input_state)) = (callbacks.input_poll, callbacks.input_state) {
+        // SAFETY: the frontend's callback, called as the libretro API specifies
+        unsafe { input_poll() };
+        keys = pressed_keys(input_state);
+    }
+    game.run_frame(keys);
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        game.render();
+        let display = &game.emulator.display;
+        // SAFETY: the frame holds width * height pixels of 4 bytes each
+        unsafe {
+            video_refresh(
+                game.frame.as_ptr().cast(),
+                display.width() as c_uint,
+                display.height() as c_uint,
+                display.width() * 4,
+            )
+        };
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        game.generate_audio();
+        // SAFETY: the buffer holds SAMPLES_PER_FRAME stereo frames
+        unsafe { audio_sample_batch(game.audio.as_ptr(), game.audio.len() / 2) };
+    }
+}
+
+/// The size of the current state plus some room for it to grow.
+/// States are stored as a 4-byte big-endian length, followed by a chiprs save state and zero padding.
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match GAME.lock().unwrap().as_ref() {
+        Some(game) => 4 + game.emulator.save_state().len() + SERIALIZE_HEADROOM,
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let game = GAME.lock().unwrap();
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    let state = game.emulator.save_state();
+    if data.is_null() || size < 4 + state.len() {
+        return false;
+    }
+    let out = std::slice::from_raw_parts_mut(data.cast::<u8>(), size);
+    out[..4].copy_from_slice(&(state.len() as u32).to_be_bytes());
+    out[4..4 + state.len()].copy_from_slice(&state);
+    out[4 + state.len()..].fill(0);
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut game = GAME.lock().unwrap();
+    let Some(game) = game.as_mut() else {
+        return false;
+    };
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let bytes = std::slice::from_raw_parts(data.cast::<u8>(), size);
+    let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+    let Some(state) = bytes[4..].get(..len) else {
+        return false;
+    };
+    if game.emulator.restore_state(state).is_err() {
+        return false;
+    }
+    game.crashed = false;
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must point to a `retro_game_info` with the program's data.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    let Some(environment) = callbacks().environment else {
+        return false;
+    };
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        (&mut format as *mut c_uint).cast(),
+    ) {
+        return false;
+    }
+    let data = std::slice::from_raw_parts((*game).data.cast::<u8>(), (*game).size);
+    let path = match (*game).path.is_null() {
+        true => "",
+        false => CStr::from_ptr((*game).path).to_str().unwrap_or(""),
+    };
+    let quirks = if path.ends_with(".sc8") {
+        Quirks::schip()
+    } else if path.ends_with(".xo8") {
+        Quirks::xo_chip()
+    } else {
+        Quirks::default()
+    };
+    let program = if path.ends_with(".8o") {
+        let Some(program) = std::str::from_utf8(data).ok().and_then(|source| {
+            assemble(source)
+                .map_err(|e| eprintln!("chiprs: {path}:{e}"))
+                .ok()
+        }) else {
+            return false;
+        };
+        program
+    } else {
+        data.to_vec()
+    };
+    let Some(loaded) = Game::new(program, quirks) else {
+        return false;
+    };
+    *GAME.lock().unwrap() = Some(loaded);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *GAME.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// Memory isn't exposed for cheats or achievements
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,224 @@
+//! A tiny libretro frontend that loads the core from its shared library and runs the IBM logo
+//! without a window, to check the core against the C ABI rather than through Rust.
+//! The declarations follow libretro.h instead of reusing the core's.
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use chiprs::{Chip8, Quirks};
+use libloading::Library;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: u32 = 1;
+
+#[repr(C)]
+struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+struct RetroSystemAvInfo {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+static PIXEL_FORMAT: AtomicU32 = AtomicU32::new(u32::MAX);
+static AUDIO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// The last frame the core sent, with its width and height
+static FRAME: Mutex<(Vec<u32>, usize, usize)> = Mutex::new((Vec::new(), 0, 0));
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            PIXEL_FORMAT.store(*data.cast::<u32>(), Ordering::SeqCst);
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(
+    data: *const c_void,
+    width: c_uint,
+    height: c_uint,
+    pitch: usize,
+) {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = data.cast::<u8>().add(y * pitch).cast::<u32>();
+        pixels.extend_from_slice(std::slice::from_raw_parts(row, width));
+    }
+    *FRAME.lock().unwrap() = (pixels, width, height);
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    AUDIO_FRAMES.fetch_add(frames, Ordering::SeqCst);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(
+    _port: c_uint,
+    _device: c_uint,
+    _index: c_uint,
+    _id: c_uint,
+) -> i16 {
+    0
+}
+
+/// The core is built next to the test executable's deps directory
+fn core_path() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join(libloading::library_filename("chiprs_libretro"))
+}
+
+#[test]
+fn test_run_ibm_logo() {
+    let program = include_bytes!("../../programs/IBM Logo.ch8");
+    unsafe {
+        let core = Library::new(core_path()).unwrap();
+        macro_rules! symbol {
+            ($name:ident: $ty:ty) => {
+                let $name = *core.get::<$ty>(stringify!($name).as_bytes()).unwrap();
+            };
+        }
+        symbol!(retro_api_version: unsafe extern "C" fn() -> c_uint);
+        symbol!(retro_set_environment: unsafe extern "C" fn(unsafe extern "C" fn(c_uint, *mut c_void) -> bool));
+        symbol!(retro_set_video_refresh: unsafe extern "C" fn(unsafe extern "C" fn(*const c_void, c_uint, c_uint, usize)));
+        symbol!(retro_set_audio_sample: unsafe extern "C" fn(unsafe extern "C" fn(i16, i16)));
+        symbol!(retro_set_audio_sample_batch: unsafe extern "C" fn(unsafe extern "C" fn(*const i16, usize) -> usize));
+        symbol!(retro_set_input_poll: unsafe extern "C" fn(unsafe extern "C" fn()));
+        symbol!(retro_set_input_state: unsafe extern "C" fn(unsafe extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16));
+        symbol!(retro_init: unsafe extern "C" fn());
+        symbol!(retro_deinit: unsafe extern "C" fn());
+        symbol!(retro_get_system_info: unsafe extern "C" fn(*mut RetroSystemInfo));
+        symbol!(retro_get_system_av_info: unsafe extern "C" fn(*mut RetroSystemAvInfo));
+        symbol!(retro_load_game: unsafe extern "C" fn(*const RetroGameInfo) -> bool);
+        symbol!(retro_unload_game: unsafe extern "C" fn());
+        symbol!(retro_run: unsafe extern "C" fn());
+        symbol!(retro_serialize_size: unsafe extern "C" fn() -> usize);
+        symbol!(retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool);
+        symbol!(retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool);
+
+        assert_eq!(retro_api_version(), 1);
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let mut info = std::mem::zeroed::<RetroSystemInfo>();
+        retro_get_system_info(&mut info);
+        assert_eq!(CStr::from_ptr(info.library_name).to_str(), Ok("chiprs"));
+        assert!(!info.need_fullpath);
+
+        let game = RetroGameInfo {
+            path: c"IBM Logo.ch8".as_ptr(),
+            data: program.as_ptr().cast(),
+            size: program.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(retro_load_game(&game));
+        assert_eq!(
+            PIXEL_FORMAT.load(Ordering::SeqCst),
+            RETRO_PIXEL_FORMAT_XRGB8888
+        );
+
+        let mut av_info = std::mem::zeroed::<RetroSystemAvInfo>();
+        retro_get_system_av_info(&mut av_info);
+        assert_eq!((av_info.base_width, av_info.base_height), (64, 32));
+        assert_eq!((av_info.max_width, av_info.max_height), (128, 64));
+        assert_eq!(av_info.fps, 60.0);
+
+        for _ in 0..10 {
+            retro_run();
+        }
+        assert_eq!(
+            AUDIO_FRAMES.load(Ordering::SeqCst),
+            10 * av_info.sample_rate as usize / 60
+        );
+
+        // the same program run directly through the library draws the same logo
+        let mut emulator = Chip8::load_program(program, Quirks::default()).unwrap();
+        for _ in 0..200 {
+            emulator.step([false; 16]).unwrap();
+        }
+        let expected: Vec<u32> = emulator
+            .display
+            .rows()
+            .flatten()
+            .map(|&color| if color == 0 { 0x000000 } else { 0xFFFFFF })
+            .collect();
+        let frame = FRAME.lock().unwrap().clone();
+        assert_eq!((frame.1, frame.2), (64, 32));
+        assert!(frame.0.contains(&0xFFFFFF));
+        assert_eq!(frame.0, expected);
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(retro_serialize(state.as_mut_ptr().cast(), state.len()));
+        retro_run();
+        assert!(retro_unserialize(state.as_ptr().cast(), state.len()));
+        assert!(!retro_unserialize(state.as_ptr().cast(), 3));
+
+        retro_unload_game();
+
+        // BNNN jumps to NNN plus V0 in a .ch8, like in the binary, rather than to XNN plus VX
+        let mut program = vec![0u8; 0x108];
+        program[..4].copy_from_slice(&[
+            0x60, 0x02, // V0 = 2
+            0xB3, 0x00, // jump 0x300 + V0
+        ]);
+        program[0x100..].copy_from_slice(&[
+            0x13, 0x00, // 0x300: loop forever, where jumping to XNN plus V3 would go
+            0xA0, 0x50, // 0x302: I = 0x050, the font's 0
+            0xD0, 0x05, // draw it at (V0, V0)
+            0x13, 0x06, // loop forever
+        ]);
+        let game = RetroGameInfo {
+            path: c"bnnn.ch8".as_ptr(),
+            data: program.as_ptr().cast(),
+            size: program.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(retro_load_game(&game));
+        retro_run();
+        let frame = FRAME.lock().unwrap().clone();
+        // the top row of the 0 is 0xF0
+        assert_eq!(
+            frame.0[2 * 64 + 2..2 * 64 + 7],
+            [0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0]
+        );
+
+        retro_unload_game();
+        retro_deinit();
+    }
+}
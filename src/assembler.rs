@@ -0,0 +1,855 @@
+//! An assembler for Octo source code (`.8o` files).
+//!
+//! Supports the instructions of CHIP-8, SUPER-CHIP and XO-CHIP, labels, `:const`, `:alias`,
+//! `:macro`, `:calc`, `:byte`, `:org`, `:call`, `:unpack`, and the structured control flow of
+//! `if ... then`, `if ... begin ... else ... end` and `loop ... while ... again`.
+//! Comparisons other than `==`, `!=`, `key` and `-key`, and string modes are not supported.
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::PROGRAM_START;
+
+/// Upper bound on macro expansions, so that a macro that invokes itself fails instead of hanging
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+/// A problem with the source code, at a 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl core::error::Error for AssembleError {}
+
+/// Assembles Octo source code into a program to be loaded at 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler {
+        tokens: tokenize(source),
+        end: Token {
+            text: String::new(),
+            line: source.lines().count().max(1),
+            column: source
+                .lines()
+                .last()
+                .map_or(1, |line| line.chars().count() + 1),
+        },
+        out: Vec::new(),
+        here: PROGRAM_START as usize,
+        constants: BTreeMap::new(),
+        labels: BTreeSet::new(),
+        aliases: BTreeMap::new(),
+        macros: BTreeMap::new(),
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        expansions: 0,
+    };
+    while let Some(token) = assembler.tokens.pop_front() {
+        assembler.statement(token)?;
+    }
+    assembler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Splits the source at whitespace, dropping `#` comments
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (column, ch) in line.chars().chain([' ']).enumerate() {
+            if ch == '#' && start.is_none() {
+                break;
+            }
+            match (ch.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(start_column)) => {
+                    tokens.push_back(Token {
+                        text: line
+                            .chars()
+                            .skip(start_column)
+                            .take(column - start_column)
+                            .collect(),
+                        line: line_idx + 1,
+                        column: start_column + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A reference to a label that hadn't been defined yet when it was used
+struct Fixup {
+    /// where the address has to be written
+    addr: usize,
+    /// F000 NNNN takes a 16-bit address, everything else a 12-bit one
+    long: bool,
+    name: Token,
+}
+
+/// An unfinished control flow structure
+enum Flow {
+    /// `if ... begin`, with the address of the jump to its `else` or `end`
+    If(usize),
+    /// `else`, with the address of the jump to its `end`
+    Else(usize),
+    /// `loop`, with its start and the address of every `while` jump out of it
+    Loop(usize, Vec<usize>),
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    /// stands in for the token after the last one, for errors at the end of the source
+    end: Token,
+    /// memory from 0x200 onwards
+    out: Vec<u8>,
+    /// the address the next instruction goes to
+    here: usize,
+    /// values of labels, `:const` and `:calc`
+    constants: BTreeMap<String, i64>,
+    /// which of the constants are labels
+    labels: BTreeSet<String>,
+    /// register names from `:alias`
+    aliases: BTreeMap<String, u8>,
+    macros: BTreeMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| self.end.error("unexpected end of file"))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected {text:?}, found {:?}", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define(&name, self.here as i64)?;
+                self.labels.insert(name.text);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.number(&value)?;
+                self.define(&name, value)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.braced_expression()?;
+                // unlike labels and :const, :calc may redefine a name
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let token = self.tokens.front().cloned().unwrap_or(self.end.clone());
+                let value = if self.peek_is("{") {
+                    self.braced_expression()?
+                } else {
+                    self.next()?;
+                    self.number(&token)?
+                };
+                self.emit(&[check_range(&token, value, -128, 0xFF)? as u8]);
+            }
+            ":org" => {
+                let addr = self.next()?;
+                self.here =
+                    check_range(&addr, self.number(&addr)?, PROGRAM_START as i64, 0xFFFF)? as usize;
+            }
+            ":call" => {
+                let addr = self.address(false)?;
+                self.emit_word(0x2000 | addr);
+            }
+            ":unpack" => {
+                // v0 := nibble and high byte of the address, v1 := low byte of the address
+                let nibble = self.next()?;
+                let nibble = check_range(&nibble, self.number(&nibble)?, 0, 0xF)? as u16;
+                let name = self.next()?;
+                let addr = self.constant(&name)?;
+                let addr = check_range(&name, addr, 0, 0xFFF)? as u16;
+                self.emit_word(0x6000 | nibble << 4 | addr >> 8);
+                self.emit_word(0x6100 | (addr & 0xFF));
+            }
+            ":breakpoint" | ":monitor" => {
+                // debugger hints for Octo's IDE
+                self.next()?;
+                if token.text == ":monitor" {
+                    self.next()?;
+                }
+            }
+            "clear" => self.emit_word(0x00E0),
+            "return" | ";" => self.emit_word(0x00EE),
+            "scroll-down" => {
+                let n = self.small_number(0xF)?;
+                self.emit_word(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.small_number(0xF)?;
+                self.emit_word(0x00D0 | n);
+            }
+            "scroll-right" => self.emit_word(0x00FB),
+            "scroll-left" => self.emit_word(0x00FC),
+            "exit" => self.emit_word(0x00FD),
+            "lores" => self.emit_word(0x00FE),
+            "hires" => self.emit_word(0x00FF),
+            "jump" => {
+                let addr = self.address(false)?;
+                self.emit_word(0x1000 | addr);
+            }
+            "jump0" => {
+                let addr = self.address(false)?;
+                self.emit_word(0xB000 | addr);
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.small_number(0xF)?;
+                self.emit_word(0xD000 | x << 8 | y << 4 | n);
+            }
+            "plane" => {
+                let n = self.small_number(0xF)?;
+                self.emit_word(0xF001 | n << 8);
+            }
+            "audio" => self.emit_word(0xF002),
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.emit_word(0xF033 | x << 8);
+            }
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                let is_save = token.text == "save";
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    self.emit_word(if is_save { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                } else {
+                    self.emit_word(if is_save { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            }
+            "saveflags" => {
+                let x = self.register()? as u16;
+                self.emit_word(0xF075 | x << 8);
+            }
+            "loadflags" => {
+                let x = self.register()? as u16;
+                self.emit_word(0xF085 | x << 8);
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit_word(opcode | x << 8);
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let then = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_word(then),
+                    "begin" => {
+                        self.emit_word(invert_skip(then));
+                        self.flow.push(Flow::If(self.here));
+                        self.emit_word(0x1000);
+                    }
+                    _ => return Err(keyword.error("expected then or begin")),
+                }
+            }
+            "else" => {
+                let Some(Flow::If(jump)) = self.flow.pop() else {
+                    return Err(token.error("else without if ... begin"));
+                };
+                self.flow.push(Flow::Else(self.here));
+                self.emit_word(0x1000);
+                self.patch_jump(&token, jump, self.here)?;
+            }
+            "end" => {
+                let (Some(Flow::If(jump)) | Some(Flow::Else(jump))) = self.flow.pop() else {
+                    return Err(token.error("end without if ... begin"));
+                };
+                self.patch_jump(&token, jump, self.here)?;
+            }
+            "loop" => self.flow.push(Flow::Loop(self.here, Vec::new())),
+            "while" => {
+                let then = self.condition()?;
+                let Some(Flow::Loop(_, exits)) = self
+                    .flow
+                    .iter_mut()
+                    .rev()
+                    .find(|flow| matches!(flow, Flow::Loop(..)))
+                else {
+                    return Err(token.error("while outside of loop ... again"));
+                };
+                exits.push(self.here + 2);
+                self.emit_word(invert_skip(then));
+                self.emit_word(0x1000);
+            }
+            "again" => {
+                let Some(Flow::Loop(start, exits)) = self.flow.pop() else {
+                    return Err(token.error("again without loop"));
+                };
+                let jump = self.here;
+                self.emit_word(0x1000);
+                self.patch_jump(&token, jump, start)?;
+                for exit in exits {
+                    self.patch_jump(&token, exit, self.here)?;
+                }
+            }
+            _ if self.is_register(&token.text) => self.register_statement(&token)?,
+            _ if self.labels.contains(&token.text) => {
+                // a bare name calls the subroutine with that label
+                let addr = self.resolve(token, false)?;
+                self.emit_word(0x2000 | addr);
+            }
+            _ if parse_number(&token.text).is_some()
+                || self.constants.contains_key(&token.text) =>
+            {
+                let value = self.number(&token)?;
+                self.emit(&[check_range(&token, value, -128, 0xFF)? as u8]);
+            }
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            _ if is_name(&token.text) => {
+                // a label that's defined further on
+                let addr = self.resolve(token, false)?;
+                self.emit_word(0x2000 | addr);
+            }
+            _ => return Err(token.error(format!("unexpected {:?}", token.text))),
+        }
+        Ok(())
+    }
+
+    /// `i := ...` and `i += vx`
+    fn index_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                if self.peek_is("long") {
+                    self.next()?;
+                    self.emit_word(0xF000);
+                    let addr = self.address(true)?;
+                    self.emit_word(addr);
+                } else if self.peek_is("hex") || self.peek_is("bighex") {
+                    let opcode = if self.next()?.text == "hex" {
+                        0xF029
+                    } else {
+                        0xF030
+                    };
+                    let x = self.register()? as u16;
+                    self.emit_word(opcode | x << 8);
+                } else {
+                    let addr = self.address(false)?;
+                    self.emit_word(0xA000 | addr);
+                }
+            }
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit_word(0xF01E | x << 8);
+            }
+            _ => return Err(op.error(format!("expected := or += after i, found {:?}", op.text))),
+        }
+        Ok(())
+    }
+
+    /// `vx := ...`, `vx += ...` and the other register operations
+    fn register_statement(&mut self, register: &Token) -> Result<(), AssembleError> {
+        let x = self.register_named(&register.text).unwrap() as u16;
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.register_named(&rhs.text).map(|y| y as u16);
+        let opcode = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            (":=", None) => match rhs.text.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    let mask = self.number(&mask)?;
+                    0xC000 | x << 8 | check_range(&rhs, mask, 0, 0xFF)? as u16
+                }
+                "delay" => 0xF007 | x << 8,
+                "key" => 0xF00A | x << 8,
+                _ => 0x6000 | x << 8 | self.byte(&rhs)?,
+            },
+            ("+=", None) => 0x7000 | x << 8 | self.byte(&rhs)?,
+            // there's no subtract immediate, so add the two's complement instead
+            ("-=", None) => 0x7000 | x << 8 | (self.byte(&rhs)?.wrapping_neg() & 0xFF),
+            _ => {
+                return Err(op.error(format!(
+                    "can't {:?} {:?} into a register",
+                    op.text, rhs.text
+                )))
+            }
+        };
+        self.emit_word(opcode);
+        Ok(())
+    }
+
+    /// Parses `vx == rhs`, `vx != rhs`, `vx key` or `vx -key`,
+    /// returning the skip instruction that runs the next instruction only when it holds.
+    fn condition(&mut self) -> Result<u16, AssembleError> {
+        let x = self.register()? as u16;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => return Ok(0xE0A1 | x << 8),
+            "-key" => return Ok(0xE09E | x << 8),
+            "==" | "!=" => {}
+            _ => {
+                return Err(op.error(format!(
+                    "unsupported comparison {:?}, expected ==, !=, key or -key",
+                    op.text
+                )))
+            }
+        }
+        let equal = op.text == "==";
+        let rhs = self.next()?;
+        Ok(match self.register_named(&rhs.text) {
+            Some(y) => (if equal { 0x9000 } else { 0x5000 }) | x << 8 | (y as u16) << 4,
+            None => (if equal { 0x4000 } else { 0x3000 }) | x << 8 | self.byte(&rhs)?,
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// Replaces a macro invocation and its arguments with the macro's body
+    fn expand_macro(&mut self, name: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(name.error("too many macro expansions, is a macro invoking itself?"));
+        }
+        let param_count = self.macros[&name.text].params.len();
+        let args = (0..param_count)
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mac = &self.macros[&name.text];
+        for token in mac.body.iter().rev() {
+            let token = match mac.params.iter().position(|param| *param == token.text) {
+                Some(idx) => args[idx].clone(),
+                None => token.clone(),
+            };
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Evaluates `{ expression }`
+    fn braced_expression(&mut self) -> Result<i64, AssembleError> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        let end = self.end.clone();
+        let mut tokens = tokens.into_iter().peekable();
+        let value = self.expression(&mut tokens, &end)?;
+        match tokens.next() {
+            Some(token) => Err(token.error(format!("unexpected {:?}", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Octo evaluates expressions right to left without operator precedence,
+    /// so `2 * 3 + 1` is 8. Parentheses group as usual.
+    fn expression(
+        &self,
+        tokens: &mut core::iter::Peekable<alloc::vec::IntoIter<Token>>,
+        end: &Token,
+    ) -> Result<i64, AssembleError> {
+        let token = tokens.next().ok_or_else(|| end.error("expected a value"))?;
+        let lhs = match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, end)?;
+                match tokens.next() {
+                    Some(close) if close.text == ")" => value,
+                    Some(close) => return Err(close.error("expected \")\"")),
+                    None => return Err(end.error("expected \")\"")),
+                }
+            }
+            "-" => return Ok(-self.expression(tokens, end)?),
+            "~" => return Ok(!self.expression(tokens, end)?),
+            "!" => return Ok((self.expression(tokens, end)? == 0) as i64),
+            "HERE" => self.here as i64,
+            _ => self.number(&token)?,
+        };
+        let Some(op) = tokens.next_if(|token| token.text != ")") else {
+            return Ok(lhs);
+        };
+        let rhs = self.expression(tokens, end)?;
+        Ok(match op.text.as_str() {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err(op.error("division by zero")),
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            _ => return Err(op.error(format!("unknown operator {:?}", op.text))),
+        })
+    }
+
+    fn name(&mut self) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if !is_name(&token.text) {
+            return Err(token.error(format!("{:?} is not a valid name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn define(&mut self, name: &Token, value: i64) -> Result<(), AssembleError> {
+        if self.constants.insert(name.text.clone(), value).is_some() {
+            return Err(name.error(format!("{} is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        self.register_named(text).is_some()
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        self.aliases
+            .get(text)
+            .copied()
+            .or_else(|| parse_register(text))
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register_named(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register, found {:?}", token.text)))
+    }
+
+    /// A number literal or the name of a constant
+    fn number(&self, token: &Token) -> Result<i64, AssembleError> {
+        parse_number(&token.text).map_or_else(|| self.constant(token), Ok)
+    }
+
+    fn constant(&self, token: &Token) -> Result<i64, AssembleError> {
+        self.constants
+            .get(&token.text)
+            .copied()
+            .ok_or_else(|| token.error(format!("{:?} is not a number or constant", token.text)))
+    }
+
+    /// An 8-bit operand, which may be negative
+    fn byte(&self, token: &Token) -> Result<u16, AssembleError> {
+        Ok(check_range(token, self.number(token)?, -128, 0xFF)? as u8 as u16)
+    }
+
+    fn small_number(&mut self, max: i64) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        Ok(check_range(&token, self.number(&token)?, 0, max)? as u16)
+    }
+
+    /// An address operand. Labels that haven't been defined yet are filled in at the end.
+    fn address(&mut self, long: bool) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        self.resolve(token, long)
+    }
+
+    fn resolve(&mut self, token: Token, long: bool) -> Result<u16, AssembleError> {
+        let max = if long { 0xFFFF } else { 0xFFF };
+        if parse_number(&token.text).is_some() || self.constants.contains_key(&token.text) {
+            return Ok(check_range(&token, self.number(&token)?, 0, max)? as u16);
+        }
+        if !is_name(&token.text) {
+            return Err(token.error(format!("expected an address, found {:?}", token.text)));
+        }
+        // the address is emitted right after this, and filled in by `finish`
+        self.fixups.push(Fixup {
+            addr: self.here,
+            long,
+            name: token,
+        });
+        Ok(0)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        let offset = self.here - PROGRAM_START as usize;
+        if self.out.len() < offset + bytes.len() {
+            self.out.resize(offset + bytes.len(), 0);
+        }
+        self.out[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        self.emit(&word.to_be_bytes());
+    }
+
+    /// Points the jump instruction at `at` to `target`
+    fn patch_jump(&mut self, token: &Token, at: usize, target: usize) -> Result<(), AssembleError> {
+        let target = check_range(token, target as i64, 0, 0xFFF)? as u16;
+        self.patch(at, 0x1000 | target);
+        Ok(())
+    }
+
+    fn patch(&mut self, at: usize, word: u16) {
+        let offset = at - PROGRAM_START as usize;
+        self.out[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    /// Fills in forward references to labels and checks for unterminated blocks
+    fn finish(mut self) -> Result<Vec<u8>, AssembleError> {
+        if !self.flow.is_empty() {
+            return Err(self
+                .end
+                .error("unexpected end of file, a block is missing its end or again"));
+        }
+        for fixup in core::mem::take(&mut self.fixups) {
+            let value = self.constant(&fixup.name).map_err(|_| {
+                fixup
+                    .name
+                    .error(format!("{} is not defined", fixup.name.text))
+            })?;
+            let offset = fixup.addr - PROGRAM_START as usize;
+            let word = u16::from_be_bytes([self.out[offset], self.out[offset + 1]]);
+            let word = if fixup.long {
+                check_range(&fixup.name, value, 0, 0xFFFF)? as u16
+            } else {
+                word | check_range(&fixup.name, value, 0, 0xFFF)? as u16
+            };
+            self.patch(fixup.addr, word);
+        }
+        Ok(self.out)
+    }
+}
+
+/// The skip instruction with the opposite condition
+fn invert_skip(opcode: u16) -> u16 {
+    match opcode & 0xF00F {
+        0x5000 => opcode ^ 0xC000, // 5XY0 <-> 9XY0
+        0x9000 => opcode ^ 0xC000,
+        0xE001 | 0xE00E => opcode ^ 0x003F, // EXA1 <-> EX9E
+        _ => opcode ^ 0x7000,               // 3XNN <-> 4XNN
+    }
+}
+
+fn check_range(token: &Token, value: i64, min: i64, max: i64) -> Result<i64, AssembleError> {
+    if !(min..=max).contains(&value) {
+        return Err(token.error(format!("{value} is out of range, expected {min} to {max}")));
+    }
+    Ok(value)
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Decimal, or hexadecimal and binary with a 0x or 0b prefix, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|ch: char| ch.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+        && text
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        :const SPEED 3
+        :alias x v4
+        :macro add-speed reg { reg += SPEED }
+        :calc HALF { 64 / 2 }
+
+        : main
+            x := HALF
+            add-speed x
+            i := sprite # forward reference
+            loop
+                sprite x v5 1
+                if v0 == 1 then x += 1
+                while x != 40
+            again
+            if v1 key begin
+                draw
+            else
+                v2 := random 0xFF
+            end
+            i := long sprite
+        : draw
+            sprite x v5 1
+            ;
+        : sprite
+            0x80
+    ";
+    #[rustfmt::skip]
+    let expected = [
+        0x64, 0x20, // 0x200: v4 := 32
+        0x74, 0x03, // 0x202: v4 += 3
+        0xA2, 0x24, // 0x204: i := 0x224
+        0xD4, 0x51, // 0x206: loop: sprite v4 v5 1
+        0x40, 0x01, // 0x208: skip if v0 != 1
+        0x74, 0x01, // 0x20A: v4 += 1
+        0x44, 0x28, // 0x20C: skip if v4 != 40
+        0x12, 0x12, // 0x20E: jump 0x212
+        0x12, 0x06, // 0x210: again
+        0xE1, 0x9E, // 0x212: skip if key v1 is pressed
+        0x12, 0x1A, // 0x214: jump 0x21A
+        0x22, 0x20, // 0x216: call 0x220
+        0x12, 0x1C, // 0x218: jump 0x21C
+        0xC2, 0xFF, // 0x21A: v2 := random 0xFF
+        0xF0, 0x00, 0x02, 0x24, // 0x21C: i := long 0x224
+        0xD4, 0x51, // 0x220: draw
+        0x00, 0xEE, // 0x222: return
+        0x80,       // 0x224: sprite
+    ];
+    assert_eq!(assemble(source), Ok(expected.to_vec()));
+}
+
+#[test]
+fn test_assemble_errors() {
+    let error = |source: &str| {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column, err.message)
+    };
+    assert_eq!(
+        error("clear\n  v0 := 256"),
+        (
+            2,
+            9,
+            "256 is out of range, expected -128 to 255".to_string()
+        )
+    );
+    assert_eq!(
+        error("jump nowhere"),
+        (1, 6, "nowhere is not defined".to_string())
+    );
+    // vz isn't a register, so it's taken to be a call to a label
+    assert_eq!(error("vz := 1"), (1, 4, "unexpected \":=\"".to_string()));
+    assert_eq!(error("loop\nclear").0, 2);
+}
+
+#[test]
+fn test_disassembly_round_trip() {
+    use crate::disasm::{Disassembly, Syntax};
+
+    let program = include_bytes!("../programs/Space Invaders [David Winter].ch8");
+    let source = Disassembly::new(program).format(Syntax::Octo);
+    assert_eq!(assemble(&source).unwrap(), program);
+}
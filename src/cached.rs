@@ -0,0 +1,392 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::{array, iter};
+
+use crate::disasm::Instruction;
+use crate::{register_range, Chip8, Chip8Error, DisplayState, MEMORY_SIZE, PLANE_COUNT};
+
+/// Addresses per page of an [`AddressMap`]
+const PAGE_SIZE: usize = 0x100;
+
+/// The longest instruction, F000 NNNN, is 4 bytes
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+/// How [`Chip8::step`] executes instructions. Every engine behaves the same, they only differ in speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Fetches and decodes the instruction at pc on every step
+    #[default]
+    Interpreter,
+    /// Decodes each instruction the first time it executes and keeps the result until memory under it is written
+    CachedDecode,
+    /// Translates each run of instructions up to the next jump, call, skip or draw into a chain of closures
+    /// the first time it executes, and keeps it until memory under it is written.
+    /// [`Chip8::run_for`] runs a whole chain at a time, [`Chip8::step`] one closure of it.
+    Recompiler,
+}
+
+/// Values by address, in pages that are only allocated once a value in them is inserted,
+/// so an emulator that runs a few KiB of code doesn't hold a table for all 64 KiB
+pub(crate) struct AddressMap<T> {
+    pages: Vec<Option<Page<T>>>,
+}
+
+type Page<T> = Box<[Option<T>; PAGE_SIZE]>;
+
+impl<T> AddressMap<T> {
+    pub(crate) fn get(&self, addr: usize) -> Option<&T> {
+        self.pages.get(addr / PAGE_SIZE)?.as_ref()?[addr % PAGE_SIZE].as_ref()
+    }
+
+    pub(crate) fn insert(&mut self, addr: usize, value: T) {
+        if self.pages.is_empty() {
+            self.pages = iter::repeat_with(|| None)
+                .take(MEMORY_SIZE / PAGE_SIZE)
+                .collect();
+        }
+        let page =
+            self.pages[addr / PAGE_SIZE].get_or_insert_with(|| Box::new(array::from_fn(|_| None)));
+        page[addr % PAGE_SIZE] = Some(value);
+    }
+
+    pub(crate) fn remove(&mut self, addr: usize) -> Option<T> {
+        self.pages.get_mut(addr / PAGE_SIZE)?.as_mut()?[addr % PAGE_SIZE].take()
+    }
+
+    /// True if nothing has been inserted since the map was created or cleared
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+impl<T> Default for AddressMap<T> {
+    fn default() -> Self {
+        AddressMap { pages: Vec::new() }
+    }
+}
+
+/// Decoded instructions, and their opcodes for error reporting, by address
+#[derive(Default)]
+pub(crate) struct DecodeCache {
+    entries: AddressMap<(u16, Instruction)>,
+}
+
+impl DecodeCache {
+    /// Forgets the instructions that overlap the written bytes, including ones that start up to 3 bytes before them
+    pub(crate) fn invalidate(&mut self, written: Range<usize>) {
+        if self.entries.is_empty() || written.is_empty() {
+            return;
+        }
+        let first = written.start + MEMORY_SIZE - (MAX_INSTRUCTION_LEN - 1);
+        for addr in first..(written.end + MEMORY_SIZE) {
+            // instructions wrap around the end of memory
+            self.entries.remove(addr % MEMORY_SIZE);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Chip8 {
+    /// Forgets the decoded and recompiled instructions that overlap the written bytes
+    pub(crate) fn invalidate_code(&mut self, written: Range<usize>) {
+        self.decode_cache.invalidate(written.clone());
+        self.blocks.invalidate(written);
+    }
+
+    /// Forgets every decoded and recompiled instruction, e.g. after replacing all of memory
+    pub(crate) fn clear_code(&mut self) {
+        self.decode_cache.clear();
+        self.blocks.clear();
+    }
+
+    /// Executes the instruction at pc with [`Engine::CachedDecode`]
+    pub(crate) fn step_cached(
+        &mut self,
+        pressed_keys: [bool; 16],
+    ) -> Result<DisplayState, Chip8Error> {
+        let pc = self.pc;
+        let (opcode, inst) = match self.decode_cache.entries.get(pc as usize) {
+            Some(&cached) => cached,
+            None => {
+                let opcode = self.read_u16(pc);
+                let inst = Instruction::decode(opcode, self.read_u16(pc.wrapping_add(2)));
+                self.decode_cache
+                    .entries
+                    .insert(pc as usize, (opcode, inst));
+                (opcode, inst)
+            }
+        };
+        self.execute(pc, opcode, inst, pressed_keys)
+    }
+
+    /// Executes an instruction that was decoded from `opcode` at `pc`, which must still be the current pc.
+    /// Instructions this engine doesn't handle itself go to the interpreter.
+    pub(crate) fn execute(
+        &mut self,
+        pc: u16,
+        opcode: u16,
+        inst: Instruction,
+        pressed_keys: [bool; 16],
+    ) -> Result<DisplayState, Chip8Error> {
+        self.pc = pc.wrapping_add(2);
+
+        let out_of_bounds = |addr| Chip8Error::MemoryOutOfBounds { pc, opcode, addr };
+        let font_character = |value: u8| {
+            if value > 15 {
+                Err(Chip8Error::InvalidFontCharacter { pc, opcode, value })
+            } else {
+                Ok(value)
+            }
+        };
+        let reg = |x: u8| x as usize;
+
+        use Instruction::*;
+        match inst {
+            Clear => {
+                self.display.clear(self.selected_planes);
+                return Ok(DisplayState::Updated);
+            }
+            Return => {
+                self.pc = self
+                    .stack
+                    .pop()
+                    .ok_or(Chip8Error::StackUnderflow { pc, opcode })?;
+            }
+            ScrollDown(n) => {
+                self.display.scroll_down(self.selected_planes, n as usize);
+                return Ok(DisplayState::Updated);
+            }
+            ScrollUp(n) => {
+                self.display.scroll_up(self.selected_planes, n as usize);
+                return Ok(DisplayState::Updated);
+            }
+            ScrollRight => {
+                self.display.scroll_right(self.selected_planes, 4);
+                return Ok(DisplayState::Updated);
+            }
+            ScrollLeft => {
+                self.display.scroll_left(self.selected_planes, 4);
+                return Ok(DisplayState::Updated);
+            }
+            Exit => self.exited = true,
+            Lores => {
+                self.display.set_hires(false);
+                return Ok(DisplayState::Updated);
+            }
+            Hires => {
+                self.display.set_hires(true);
+                return Ok(DisplayState::Updated);
+            }
+            Jump(addr) => self.pc = addr,
+            Call(addr) => {
+                if !self.stack.push(self.pc) {
+                    return Err(Chip8Error::StackOverflow { pc, opcode });
+                }
+                self.pc = addr;
+            }
+            SkipIfEqual { x, value } => {
+                if self.registers[reg(x)] == value {
+                    self.skip_instruction();
+                }
+            }
+            SkipIfNotEqual { x, value } => {
+                if self.registers[reg(x)] != value {
+                    self.skip_instruction();
+                }
+            }
+            SkipIfRegistersEqual { x, y } => {
+                if self.registers[reg(x)] == self.registers[reg(y)] {
+                    self.skip_instruction();
+                }
+            }
+            StoreRange { x, y } => {
+                let (x, y) = (reg(x), reg(y));
+                let range = self.index_range(x.abs_diff(y) + 1).map_err(out_of_bounds)?;
+                for (addr, reg) in range.clone().zip(register_range(x, y)) {
+                    self.memory[addr] = self.registers[reg];
+                }
+                self.invalidate_code(range);
+            }
+            LoadRange { x, y } => {
+                let (x, y) = (reg(x), reg(y));
+                let range = self.index_range(x.abs_diff(y) + 1).map_err(out_of_bounds)?;
+                for (addr, reg) in range.zip(register_range(x, y)) {
+                    self.registers[reg] = self.memory[addr];
+                }
+            }
+            Set { x, value } => self.registers[reg(x)] = value,
+            Add { x, value } => self.registers[reg(x)] = self.registers[reg(x)].wrapping_add(value),
+            Copy { x, y } => self.registers[reg(x)] = self.registers[reg(y)],
+            Or { x, y } => {
+                self.registers[reg(x)] |= self.registers[reg(y)];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            And { x, y } => {
+                self.registers[reg(x)] &= self.registers[reg(y)];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            Xor { x, y } => {
+                self.registers[reg(x)] ^= self.registers[reg(y)];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            AddRegister { x, y } => {
+                let (sum, carry) = self.registers[reg(x)].overflowing_add(self.registers[reg(y)]);
+                self.registers[reg(x)] = sum;
+                self.registers[0xF] = carry as u8;
+            }
+            Sub { x, y } => {
+                let (vx, vy) = (self.registers[reg(x)], self.registers[reg(y)]);
+                self.registers[reg(x)] = vx.wrapping_sub(vy);
+                self.registers[0xF] = (vx >= vy) as u8;
+            }
+            SubReversed { x, y } => {
+                let (vx, vy) = (self.registers[reg(x)], self.registers[reg(y)]);
+                self.registers[reg(x)] = vy.wrapping_sub(vx);
+                self.registers[0xF] = (vy >= vx) as u8;
+            }
+            ShiftRight { x, y } => {
+                if !self.quirks.shift_in_place {
+                    self.registers[reg(x)] = self.registers[reg(y)];
+                }
+                let shifted_out = self.registers[reg(x)] & 0x1;
+                self.registers[reg(x)] >>= 1;
+                self.registers[0xF] = shifted_out;
+            }
+            ShiftLeft { x, y } => {
+                if !self.quirks.shift_in_place {
+                    self.registers[reg(x)] = self.registers[reg(y)];
+                }
+                let shifted_out = self.registers[reg(x)] >> 7;
+                self.registers[reg(x)] <<= 1;
+                self.registers[0xF] = shifted_out;
+            }
+            SkipIfRegistersNotEqual { x, y } => {
+                if self.registers[reg(x)] != self.registers[reg(y)] {
+                    self.skip_instruction();
+                }
+            }
+            SetIndex(addr) => self.index_reg = addr,
+            JumpOffset(addr) => {
+                let x = (addr >> 8) as u8;
+                let offset_reg = if self.quirks.jump_uses_vx { x } else { 0 };
+                self.pc = addr + self.registers[reg(offset_reg)] as u16;
+            }
+            Random { x, mask } => self.registers[reg(x)] = self.rng.next_u8() & mask,
+            Draw { x, y, height } => {
+                let (sprite_width, sprite_height) = if height == 0 {
+                    (16, 16)
+                } else {
+                    (8, height as usize)
+                };
+                let collision = self
+                    .draw_sprite(
+                        self.registers[reg(x)] as usize,
+                        self.registers[reg(y)] as usize,
+                        sprite_width,
+                        sprite_height,
+                    )
+                    .map_err(out_of_bounds)?;
+                self.registers[0xF] = collision as u8;
+                return Ok(DisplayState::Updated);
+            }
+            SkipIfKey(x) => {
+                let vx = self.registers[reg(x)];
+                if vx < 16 && pressed_keys[vx as usize] {
+                    self.skip_instruction();
+                }
+            }
+            SkipIfNotKey(x) => {
+                let vx = self.registers[reg(x)];
+                if vx >= 16 || !pressed_keys[vx as usize] {
+                    self.skip_instruction();
+                }
+            }
+            SetIndexLong(addr) => {
+                self.index_reg = addr;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            SelectPlanes(planes) => self.selected_planes = planes & ((1 << PLANE_COUNT) - 1),
+            LoadAudio => {
+                let mut buffer = [0u8; 16];
+                let range = self.index_range(16).map_err(out_of_bounds)?;
+                buffer.copy_from_slice(&self.memory[range]);
+                self.audio_buffer = Some(buffer);
+            }
+            SetPitch(x) => self.pitch = self.registers[reg(x)],
+            GetDelay(x) => self.registers[reg(x)] = self.delay_timer,
+            SetDelay(x) => self.delay_timer = self.registers[reg(x)],
+            SetSound(x) => self.sound_timer = self.registers[reg(x)],
+            AddIndex(x) => {
+                let (index, overflow) = self
+                    .index_reg
+                    .overflowing_add(self.registers[reg(x)] as u16);
+                self.index_reg = index;
+                if overflow {
+                    self.registers[0xF] = 1;
+                }
+            }
+            WaitKey(x) => match pressed_keys.iter().position(|&pressed| pressed) {
+                Some(idx) => self.registers[reg(x)] = idx as u8,
+                None => self.pc = pc,
+            },
+            SmallFont(x) => {
+                let vx = font_character(self.registers[reg(x)])?;
+                self.index_reg = self.fonts.small_digit_addr(vx);
+            }
+            BigFont(x) => {
+                let vx = font_character(self.registers[reg(x)])?;
+                self.index_reg = self.fonts.big_digit_addr(vx);
+            }
+            Bcd(x) => {
+                let value = self.registers[reg(x)];
+                let range = self.index_range(3).map_err(out_of_bounds)?;
+                self.memory[range.clone()].copy_from_slice(&[
+                    value / 100,
+                    value / 10 % 10,
+                    value % 10,
+                ]);
+                self.invalidate_code(range);
+            }
+            Store(x) => {
+                let x = reg(x);
+                let range = self.index_range(x + 1).map_err(out_of_bounds)?;
+                self.memory[range.clone()].copy_from_slice(&self.registers[..=x]);
+                self.invalidate_code(range);
+                if self.quirks.load_store_increments_index {
+                    self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
+                }
+            }
+            Load(x) => {
+                let x = reg(x);
+                let range = self.index_range(x + 1).map_err(out_of_bounds)?;
+                self.registers[..=x].copy_from_slice(&self.memory[range]);
+                if self.quirks.load_store_increments_index {
+                    self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
+                }
+            }
+            SaveFlags(x) => self.flags[..=reg(x)].copy_from_slice(&self.registers[..=reg(x)]),
+            LoadFlags(x) => self.registers[..=reg(x)].copy_from_slice(&self.flags[..=reg(x)]),
+            // machine code calls and the opcodes the decoder doesn't recognize,
+            // some of which the interpreter accepts, e.g. 9XY1
+            MachineCall(_) | Unknown(_) => {
+                self.pc = pc;
+                return self.interpret(pressed_keys);
+            }
+        }
+        Ok(DisplayState::NotUpdated)
+    }
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chiprs::Display;
+
+/// Colors for each combination of the XO-CHIP bitplanes, the same as the native window
+const PALETTE: [[u8; 3]; 4] = [
+    [0, 0, 0],
+    [0xFF, 0xFF, 0xFF],
+    [0xFF, 0x66, 0x00],
+    [0x66, 0x22, 0x00],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Binary PBM, which only has black and white, so any color counts as a set pixel
+    Pbm,
+    /// Indexed PNG with the native window's colors
+    Png,
+}
+
+/// Writes frames of a headless run to image files.
+///
+/// With neither `every` nor `on_update` only the last frame is written, to the output path itself.
+/// Otherwise each captured frame goes to its own file with the frame number after the file stem,
+/// e.g. `out-000120.png`.
+pub struct FrameCapture {
+    path: PathBuf,
+    format: Format,
+    /// capture every Kth frame
+    every: Option<u64>,
+    /// capture the frames where an instruction changed the display
+    on_update: bool,
+    frame: u64,
+}
+
+impl FrameCapture {
+    pub fn new(path: PathBuf, every: Option<u64>, on_update: bool) -> Result<Self, Box<dyn Error>> {
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => Format::Pbm,
+            Some("png") => Format::Png,
+            _ => return Err(format!("{path:?} should end in .pbm or .png").into()),
+        };
+        Ok(FrameCapture {
+            path,
+            format,
+            every,
+            on_update,
+            frame: 0,
+        })
+    }
+
+    /// Called at the end of every frame
+    pub fn frame(&mut self, display: &Display, updated: bool) -> Result<(), Box<dyn Error>> {
+        let every = self.every.is_some_and(|k| self.frame.is_multiple_of(k));
+        if every || (self.on_update && updated) {
+            let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = format!("{stem}-{:06}", self.frame);
+            let path = self
+                .path
+                .with_file_name(name)
+                .with_extension(self.extension());
+            self.write(&path, display)?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Called once the run is over
+    pub fn finish(&self, display: &Display) -> Result<(), Box<dyn Error>> {
+        if self.every.is_none() && !self.on_update {
+            self.write(&self.path, display)?;
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.format {
+            Format::Pbm => "pbm",
+            Format::Png => "png",
+        }
+    }
+
+    fn write(&self, path: &Path, display: &Display) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| format!("can't create {path:?}: {e}"))?;
+        let mut out = BufWriter::new(file);
+        match self.format {
+            Format::Pbm => write_pbm(&mut out, display)?,
+            Format::Png => write_png(&mut out, display)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_pbm(out: &mut impl Write, display: &Display) -> std::io::Result<()> {
+    write!(out, "P4\n{} {}\n", display.width(), display.height())?;
+    for row in display.rows() {
+        // 8 pixels to a byte, most significant bit first, and 1 is black
+        for chunk in row.chunks(8) {
+            let byte = chunk.iter().enumerate().fold(0u8, |byte, (i, &color)| {
+                byte | ((color != 0) as u8) << (7 - i)
+            });
+            out.write_all(&[byte])?;
+        }
+    }
+    Ok(())
+}
+
+fn write_png(out: &mut impl Write, display: &Display) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(out, display.width() as u32, display.height() as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(PALETTE.concat());
+    let mut writer = encoder.write_header()?;
+    let pixels: Vec<u8> = display.rows().flatten().copied().collect();
+    writer.write_image_data(&pixels)
+}
+
+#[test]
+fn test_write_pbm() {
+    let mut display = Display::default();
+    display.set(0, 0, 1);
+    display.set(9, 0, 3);
+    let mut out = Vec::new();
+    write_pbm(&mut out, &display).unwrap();
+    let header = b"P4\n64 32\n";
+    assert_eq!(&out[..header.len()], header);
+    // 8 bytes per row
+    assert_eq!(out.len(), header.len() + 8 * 32);
+    assert_eq!(out[header.len()..][..2], [0x80, 0x40]);
+}
@@ -0,0 +1,399 @@
+/// What the CPU is connected to: memory, the I/O ports of OUT and INP, and the EF flag inputs
+pub(crate) trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    /// OUT 1 to 7 put a byte from memory on the data bus for port `port`
+    fn output(&mut self, port: u8, value: u8);
+    /// INP 1 to 7 read a byte from port `port` into memory and D
+    fn input(&mut self, port: u8) -> u8;
+    /// Whether EF1 to EF4 is asserted
+    fn flag(&self, flag: u8) -> bool;
+}
+
+/// The RCA CDP1802 COSMAC microprocessor.
+/// Any of its 16 registers can be the program counter, selected by P, or the data pointer, selected by X.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cdp1802 {
+    pub(crate) r: [u16; 16],
+    pub(crate) p: u8,
+    pub(crate) x: u8,
+    /// The accumulator
+    pub(crate) d: u8,
+    /// The carry flag, set when there's no borrow after a subtraction
+    pub(crate) df: bool,
+    /// X and P saved by an interrupt or MARK
+    pub(crate) t: u8,
+    /// Interrupt enable
+    pub(crate) ie: bool,
+    /// The output flip-flop, which drives the VIP's speaker
+    pub(crate) q: bool,
+    /// Set by IDL, the CPU does nothing until a DMA or interrupt request
+    pub(crate) idle: bool,
+}
+
+impl Cdp1802 {
+    /// The state after a reset: R0 is the program counter and starts at 0, and interrupts are enabled
+    pub(crate) fn new() -> Self {
+        Cdp1802 {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    /// Executes one instruction and returns how many machine cycles it took
+    pub(crate) fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        let opcode = self.fetch(bus);
+        let n = opcode & 0xF;
+        let reg = n as usize;
+        match opcode >> 4 {
+            // IDL
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[reg]),
+            // INC
+            0x1 => self.r[reg] = self.r[reg].wrapping_add(1),
+            // DEC
+            0x2 => self.r[reg] = self.r[reg].wrapping_sub(1),
+            0x3 => {
+                let target = self.fetch(bus);
+                if self.short_branch_condition(n, bus) {
+                    let pc = &mut self.r[self.p as usize];
+                    // the branch target is in the page of the byte after the opcode
+                    *pc = (pc.wrapping_sub(1) & 0xFF00) | target as u16;
+                }
+            }
+            // LDA
+            0x4 => {
+                self.d = bus.read(self.r[reg]);
+                self.r[reg] = self.r[reg].wrapping_add(1);
+            }
+            // STR
+            0x5 => bus.write(self.r[reg], self.d),
+            // IRX
+            0x6 if n == 0 => self.inc_x(),
+            // OUT 1-7
+            0x6 if n < 8 => {
+                let value = bus.read(self.r[self.x as usize]);
+                bus.output(n, value);
+                self.inc_x();
+            }
+            // 68 isn't used on the 1802
+            0x6 if n == 8 => {}
+            // INP 1-7
+            0x6 => {
+                self.d = bus.input(n - 8);
+                bus.write(self.r[self.x as usize], self.d);
+            }
+            0x7 => self.execute_7n(n, bus),
+            // GLO
+            0x8 => self.d = self.r[reg] as u8,
+            // GHI
+            0x9 => self.d = (self.r[reg] >> 8) as u8,
+            // PLO
+            0xA => self.r[reg] = (self.r[reg] & 0xFF00) | self.d as u16,
+            // PHI
+            0xB => self.r[reg] = (self.r[reg] & 0x00FF) | (self.d as u16) << 8,
+            0xC => {
+                self.long_branch(n, bus);
+                return 3;
+            }
+            // SEP
+            0xD => self.p = n,
+            // SEX
+            0xE => self.x = n,
+            _ => self.execute_fn(n, bus),
+        }
+        2
+    }
+
+    /// Responds to an interrupt request if interrupts are enabled: saves X and P in T,
+    /// then continues with R1 as the program counter and R2 as the data pointer.
+    /// Returns whether the interrupt was taken, which takes a machine cycle.
+    pub(crate) fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    /// A DMA output cycle, which reads the byte at R0 for a device and increments R0
+    pub(crate) fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /// Reads the byte at the program counter and increments it
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let pc = &mut self.r[self.p as usize];
+        let value = bus.read(*pc);
+        *pc = pc.wrapping_add(1);
+        value
+    }
+
+    fn inc_x(&mut self) {
+        let x = self.x as usize;
+        self.r[x] = self.r[x].wrapping_add(1);
+    }
+
+    /// 3N: BR, BQ, BZ, BDF, B1-B4, then SKP and the inverted conditions
+    fn short_branch_condition(&self, n: u8, bus: &impl Bus) -> bool {
+        let condition = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag - 3),
+        };
+        condition != (n >= 8)
+    }
+
+    /// CN: the long branches, which jump to the next two bytes, and the long skips, which skip them
+    fn long_branch(&mut self, n: u8, bus: &mut impl Bus) {
+        let pc = self.r[self.p as usize];
+        let (is_skip, condition) = match n {
+            // LBR, LBQ, LBZ and LBDF
+            0x0 => (false, true),
+            0x1 => (false, self.q),
+            0x2 => (false, self.d == 0),
+            0x3 => (false, self.df),
+            // NOP, LSNQ, LSNZ and LSNF
+            0x4 => (true, false),
+            0x5 => (true, !self.q),
+            0x6 => (true, self.d != 0),
+            0x7 => (true, !self.df),
+            // LSKP, LBNQ, LBNZ and LBNF
+            0x8 => (true, true),
+            0x9 => (false, !self.q),
+            0xA => (false, self.d != 0),
+            0xB => (false, !self.df),
+            // LSIE, LSQ, LSZ and LSDF
+            0xC => (true, self.ie),
+            0xD => (true, self.q),
+            0xE => (true, self.d == 0),
+            _ => (true, self.df),
+        };
+        self.r[self.p as usize] = if condition && !is_skip {
+            let high = bus.read(pc);
+            let low = bus.read(pc.wrapping_add(1));
+            u16::from_be_bytes([high, low])
+        } else if condition || !is_skip {
+            // a taken skip and a branch that isn't taken both continue after the two bytes
+            pc.wrapping_add(2)
+        } else {
+            pc
+        };
+    }
+
+    fn execute_7n(&mut self, n: u8, bus: &mut impl Bus) {
+        let rx = self.r[self.x as usize];
+        match n {
+            // RET and DIS restore X and P from memory and set or clear IE
+            0x0 | 0x1 => {
+                let xp = bus.read(rx);
+                self.inc_x();
+                self.x = xp >> 4;
+                self.p = xp & 0xF;
+                self.ie = n == 0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = bus.read(rx);
+                self.inc_x();
+            }
+            // STXD
+            0x3 => {
+                bus.write(rx, self.d);
+                self.r[self.x as usize] = rx.wrapping_sub(1);
+            }
+            // ADC
+            0x4 => self.add(bus.read(rx), self.df),
+            // SDB
+            0x5 => self.subtract(bus.read(rx), self.d, self.df),
+            // SHRC
+            0x6 => {
+                let carry = self.d & 1 == 1;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            // SMB
+            0x7 => self.subtract(self.d, bus.read(rx), self.df),
+            // SAV
+            0x8 => bus.write(rx, self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ and SEQ
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // ADCI
+            0xC => {
+                let value = self.fetch(bus);
+                self.add(value, self.df);
+            }
+            // SDBI
+            0xD => {
+                let value = self.fetch(bus);
+                self.subtract(value, self.d, self.df);
+            }
+            // SHLC
+            0xE => {
+                let carry = self.d >> 7 == 1;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            // SMBI
+            _ => {
+                let value = self.fetch(bus);
+                self.subtract(self.d, value, self.df);
+            }
+        }
+    }
+
+    /// FN: the logic and arithmetic operations with memory at R(X), and from F8 on with an immediate byte
+    fn execute_fn(&mut self, n: u8, bus: &mut impl Bus) {
+        let value = match n {
+            // SHR and SHL only use D
+            0x6 | 0xE => 0,
+            0x0..=0x7 => bus.read(self.r[self.x as usize]),
+            _ => self.fetch(bus),
+        };
+        match n & 0x7 {
+            // LDX and LDI
+            0x0 => self.d = value,
+            // OR and ORI
+            0x1 => self.d |= value,
+            // AND and ANI
+            0x2 => self.d &= value,
+            // XOR and XRI
+            0x3 => self.d ^= value,
+            // ADD and ADI
+            0x4 => self.add(value, false),
+            // SD and SDI
+            0x5 => self.subtract(value, self.d, true),
+            // SHR and SHL
+            0x6 if n == 0x6 => {
+                self.df = self.d & 1 == 1;
+                self.d >>= 1;
+            }
+            0x6 => {
+                self.df = self.d >> 7 == 1;
+                self.d <<= 1;
+            }
+            // SM and SMI
+            _ => self.subtract(self.d, value, true),
+        }
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// `a - b`, borrowing if `no_borrow` is false. DF is set if the result doesn't borrow.
+    fn subtract(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let difference = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+#[cfg(test)]
+struct TestBus {
+    memory: [u8; 0x100],
+    outputs: [u8; 8],
+    flags: [bool; 4],
+}
+
+#[cfg(test)]
+impl Bus for TestBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize % 0x100]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize % 0x100] = value;
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        self.outputs[port as usize] = value;
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        0x10 + port
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.flags[flag as usize - 1]
+    }
+}
+
+#[test]
+fn test_cdp1802() {
+    let program = [
+        0xF8, 0x40, // LDI 0x40
+        0xA3, // PLO 3
+        0xF8, 0xF0, // LDI 0xF0
+        0xFC, 0x20, // ADI 0x20, overflows
+        0x53, // STR 3
+        0x7C, 0x00, // ADCI 0, adds the carry
+        0xE3, // SEX 3
+        0xF7, // SM, 0x11 - 0x10 doesn't borrow
+        0x63, // OUT 3, R3 becomes 0x41
+        0x6D, // INP 5
+        0xFE, // SHL
+        0x33, 0x14, // BDF 0x14, not taken because 0x15 << 1 doesn't carry
+        0x7B, // SEQ
+        0x34, 0x15, // B1 0x15, taken
+        0x00, // IDL
+        0xC0, 0x00, 0x30, // LBR 0x30
+    ];
+    let mut bus = TestBus {
+        memory: [0; 0x100],
+        outputs: [0; 8],
+        flags: [true, false, false, false],
+    };
+    bus.memory[..program.len()].copy_from_slice(&program);
+    let mut cpu = Cdp1802::new();
+    let mut cycles = 0;
+    while cpu.r[0] != 0x30 {
+        cycles += cpu.step(&mut bus);
+    }
+    assert_eq!(cycles, 14 * 2 + 3);
+    assert_eq!(cpu.d, 0x2A);
+    assert!(!cpu.df);
+    assert!(cpu.q);
+    assert_eq!(bus.memory[0x40], 0x10);
+    assert_eq!(bus.outputs[3], 0x10);
+    assert_eq!(bus.memory[0x41], 0x15);
+    assert_eq!(cpu.r[3], 0x41);
+
+    // an interrupt saves X and P, and RET restores them
+    assert!(cpu.interrupt());
+    assert_eq!((cpu.t, cpu.p, cpu.x, cpu.ie), (0x30, 1, 2, false));
+    assert!(!cpu.interrupt());
+    cpu.r[1] = 0x50;
+    cpu.r[2] = 0x60;
+    bus.memory[0x50] = 0x70; // RET
+    bus.memory[0x60] = 0x23;
+    cpu.step(&mut bus);
+    assert_eq!((cpu.p, cpu.x, cpu.ie), (3, 2, true));
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chiprs::debugger::{self, Access, Debugger, OpcodePattern, StopReason, Watchpoint};
+use chiprs::Chip8;
+
+const HELP: &str = "\
+commands:
+  s, step                        execute one instruction
+  n, next                        step, running 2NNN subroutine calls until they return
+  c, continue                    run until a breakpoint or watchpoint, or ctrl-c
+  b, break <addr>                break when pc reaches addr
+  bo, break-opcode <pattern>     break before any matching instruction, e.g. DXYN or FX55
+  w, watch <start>[..<end>] [r|w|rw]
+                                 break before an instruction accesses memory in start..end
+  d, delete [<addr>]             delete the breakpoint at addr, or every breakpoint and watchpoint
+  i, info                        list breakpoints and watchpoints
+  r, regs                        show registers, timers and stack
+  x <addr> [<len>]               dump len bytes of memory, 16 by default
+  q, quit                        exit the emulator
+addresses and lengths are hexadecimal";
+
+pub enum ConsoleAction {
+    Resume,
+    Quit,
+}
+
+/// Reads debugger commands from stdin whenever the [`Debugger`] stops execution.
+pub struct DebugConsole {
+    debugger: Debugger,
+    /// set by ctrl-c to break into the running program
+    interrupted: Arc<AtomicBool>,
+}
+
+impl DebugConsole {
+    /// The console stops before the first instruction, so breakpoints can be set up front.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let handler_flag = interrupted.clone();
+        ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst))?;
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        Ok(DebugConsole {
+            debugger,
+            interrupted,
+        })
+    }
+
+    /// Checks for a breakpoint, watchpoint or ctrl-c before the next instruction.
+    pub fn check(&mut self, chip8: &Chip8) -> Option<StopReason> {
+        if self.interrupted.swap(false, Ordering::SeqCst) {
+            self.debugger.pause();
+        }
+        self.debugger.check(chip8)
+    }
+
+    /// Prints why execution stopped and reads commands until one resumes execution.
+    pub fn prompt(&mut self, reason: &StopReason, chip8: &Chip8) -> io::Result<ConsoleAction> {
+        println!("{reason}");
+        print!("{}", debugger::format_state(chip8));
+        let stdin = io::stdin();
+        loop {
+            print!("(chiprs) ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(ConsoleAction::Quit);
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, args)) = words.split_first() else {
+                continue;
+            };
+            let result = match command {
+                "s" | "step" => {
+                    self.debugger.step();
+                    break;
+                }
+                "n" | "next" => {
+                    self.debugger.step_over(chip8);
+                    break;
+                }
+                "c" | "continue" => {
+                    self.debugger.resume();
+                    break;
+                }
+                "q" | "quit" => return Ok(ConsoleAction::Quit),
+                "b" | "break" => self.add_breakpoint(args),
+                "bo" | "break-opcode" => self.add_opcode_breakpoint(args),
+                "w" | "watch" => self.add_watchpoint(args),
+                "d" | "delete" => self.delete(args),
+                "i" | "info" => {
+                    self.print_breakpoints();
+                    Ok(())
+                }
+                "r" | "regs" => {
+                    print!("{}", debugger::format_state(chip8));
+                    Ok(())
+                }
+                "x" => dump_memory(chip8, args),
+                "h" | "help" => {
+                    println!("{HELP}");
+                    Ok(())
+                }
+                _ => Err(format!("unknown command {command:?}, try help")),
+            };
+            if let Err(message) = result {
+                println!("{message}");
+            }
+        }
+        // don't stop straight away for a ctrl-c pressed at the prompt
+        self.interrupted.store(false, Ordering::SeqCst);
+        Ok(ConsoleAction::Resume)
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let [addr] = args else {
+            return Err("usage: break <addr>".to_string());
+        };
+        self.debugger.add_breakpoint(parse_hex(addr)?);
+        Ok(())
+    }
+
+    fn add_opcode_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let [pattern] = args else {
+            return Err("usage: break-opcode <pattern>".to_string());
+        };
+        self.debugger
+            .add_opcode_breakpoint(pattern.parse::<OpcodePattern>()?);
+        Ok(())
+    }
+
+    fn add_watchpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let (range, access) = match args {
+            [range] => (range, "rw"),
+            [range, access] => (range, *access),
+            _ => return Err("usage: watch <start>[..<end>] [r|w|rw]".to_string()),
+        };
+        let range = match range.split_once("..") {
+            Some((start, end)) => {
+                let (start, end) = (parse_hex(start)?, parse_hex(end)?);
+                if end <= start {
+                    return Err(format!("{range} is empty"));
+                }
+                start..=end - 1
+            }
+            None => {
+                let addr = parse_hex(range)?;
+                addr..=addr
+            }
+        };
+        let access = match access {
+            "r" => Access::Read,
+            "w" => Access::Write,
+            "rw" => Access::ReadWrite,
+            _ => return Err(format!("expected r, w or rw, got {access:?}")),
+        };
+        self.debugger.add_watchpoint(Watchpoint { range, access });
+        Ok(())
+    }
+
+    fn delete(&mut self, args: &[&str]) -> Result<(), String> {
+        match args {
+            [] => self.debugger.clear(),
+            [addr] => {
+                let addr = parse_hex(addr)?;
+                if !self.debugger.remove_breakpoint(addr) {
+                    return Err(format!("no breakpoint at {addr:04X}"));
+                }
+            }
+            _ => return Err("usage: delete [<addr>]".to_string()),
+        }
+        Ok(())
+    }
+
+    fn print_breakpoints(&self) {
+        for addr in self.debugger.breakpoints() {
+            println!("break at {addr:04X}");
+        }
+        for pattern in self.debugger.opcode_breakpoints() {
+            println!("break on {pattern}");
+        }
+        for Watchpoint { range, access } in self.debugger.watchpoints() {
+            println!(
+                "watch {:04X}..={:04X} {access:?}",
+                range.start(),
+                range.end()
+            );
+        }
+    }
+}
+
+fn dump_memory(chip8: &Chip8, args: &[&str]) -> Result<(), String> {
+    let (addr, len) = match args {
+        [addr] => (parse_hex(addr)?, 16),
+        [addr, len] => (parse_hex(addr)?, parse_hex(len)?),
+        _ => return Err("usage: x <addr> [<len>]".to_string()),
+    };
+    let start = addr as usize;
+    print!(
+        "{}",
+        debugger::format_memory(chip8, start..start + len as usize)
+    );
+    Ok(())
+}
+
+/// Parses a hexadecimal number, with or without a 0x prefix
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(digits, 16)
+        .map_err(|_| format!("expected a hexadecimal address, got {s:?}"))
+}
@@ -0,0 +1,498 @@
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+use core::ops::{Range, RangeInclusive};
+use core::str::FromStr;
+
+use crate::disasm::Instruction;
+use crate::{Chip8, Chip8Error, MEMORY_SIZE};
+
+/// How an instruction uses memory through I
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Only used by watchpoints, to stop on either kind of access
+    ReadWrite,
+}
+
+impl Access {
+    fn includes(self, other: Access) -> bool {
+        self == Access::ReadWrite || self == other
+    }
+}
+
+/// Stops execution before an instruction that accesses any address in `range`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+}
+
+/// Matches a class of opcodes, written the way they usually are in documentation,
+/// with X, Y and N standing for any nibble, e.g. `DXYN` or `FX55`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    /// bits that must match
+    pub mask: u16,
+    pub value: u16,
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.chars().count() != 4 {
+            return Err(format!(
+                "expected an opcode pattern of 4 nibbles like DXYN, got {s:?}"
+            ));
+        }
+        let mut pattern = OpcodePattern { mask: 0, value: 0 };
+        for c in s.chars() {
+            pattern.mask <<= 4;
+            pattern.value <<= 4;
+            match c.to_digit(16) {
+                Some(nibble) => {
+                    pattern.mask |= 0xF;
+                    pattern.value |= nibble as u16;
+                }
+                None if matches!(c.to_ascii_uppercase(), 'X' | 'Y' | 'N') => {}
+                None => return Err(format!("unexpected {c:?} in opcode pattern {s:?}")),
+            }
+        }
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, wildcard) in ['X', 'X', 'Y', 'N'].iter().enumerate() {
+            let shift = 12 - 4 * idx;
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "{wildcard}")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why the debugger paused execution. Execution pauses before the instruction at `pc` runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// A step or step-over finished
+    Step {
+        pc: u16,
+    },
+    Breakpoint {
+        pc: u16,
+    },
+    OpcodeBreakpoint {
+        pc: u16,
+        opcode: u16,
+        pattern: OpcodePattern,
+    },
+    Watchpoint {
+        pc: u16,
+        opcode: u16,
+        /// the first address accessed inside the watched range
+        addr: u16,
+        access: Access,
+    },
+    /// The instruction is a 2NNN call with the stack full, which would fail with [`Chip8Error::StackOverflow`]
+    StackOverflow {
+        pc: u16,
+        opcode: u16,
+    },
+    /// The instruction is 00EE with the stack empty, which would fail with [`Chip8Error::StackUnderflow`]
+    StackUnderflow {
+        pc: u16,
+    },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step { pc } => write!(f, "stepped to {pc:#06x}"),
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at {pc:#06x}"),
+            StopReason::OpcodeBreakpoint {
+                pc,
+                opcode,
+                pattern,
+            } => write!(f, "{opcode:04X} at {pc:#06x} matches {pattern}"),
+            StopReason::Watchpoint {
+                pc,
+                opcode,
+                addr,
+                access,
+            } => {
+                let verb = if *access == Access::Write {
+                    "writes"
+                } else {
+                    "reads"
+                };
+                write!(
+                    f,
+                    "{opcode:04X} at {pc:#06x} {verb} watched address {addr:#06x}"
+                )
+            }
+            StopReason::StackOverflow { pc, opcode } => write!(
+                f,
+                "{opcode:04X} at {pc:#06x} calls a subroutine with the stack full"
+            ),
+            StopReason::StackUnderflow { pc } => write!(
+                f,
+                "00EE at {pc:#06x} returns from a subroutine with the stack empty"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    /// stop before the next instruction
+    Step,
+    /// stop once a subroutine call returns to `pc` with the stack back at `depth`
+    StepOver {
+        pc: u16,
+        depth: usize,
+    },
+}
+
+/// Breakpoints, watchpoints and single-stepping around [`Chip8::step`].
+/// It also stops before a call or return that would overflow or underflow the stack.
+///
+/// Call [`Debugger::check`] before every step. When it returns a [`StopReason`], inspect the emulator,
+/// then call [`Debugger::resume`], [`Debugger::step`] or [`Debugger::step_over`] and keep stepping.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    mode: Mode,
+    /// set after stopping, so that resuming executes the instruction that was stopped at
+    resuming: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            opcode_breakpoints: Vec::new(),
+            mode: Mode::Run,
+            resuming: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Returns false if there was no breakpoint at `pc`
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        self.opcode_breakpoints.push(pattern);
+    }
+
+    pub fn opcode_breakpoints(&self) -> &[OpcodePattern] {
+        &self.opcode_breakpoints
+    }
+
+    /// Removes every breakpoint and watchpoint
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.opcode_breakpoints.clear();
+    }
+
+    /// Pauses before the next instruction, e.g. to break into a running program
+    pub fn pause(&mut self) {
+        self.mode = Mode::Step;
+        self.resuming = false;
+    }
+
+    /// Runs until the next breakpoint or watchpoint
+    pub fn resume(&mut self) {
+        self.mode = Mode::Run;
+    }
+
+    /// Executes one instruction, then stops
+    pub fn step(&mut self) {
+        self.mode = Mode::Step;
+    }
+
+    /// Like [`Debugger::step`], but runs a 2NNN subroutine call until it returns.
+    /// Breakpoints inside the subroutine still stop execution.
+    pub fn step_over(&mut self, chip8: &Chip8) {
+        let opcode = chip8.read_u16(chip8.pc);
+        self.mode = if opcode & 0xF000 == 0x2000 {
+            Mode::StepOver {
+                pc: chip8.pc.wrapping_add(2),
+                depth: chip8.stack().len(),
+            }
+        } else {
+            Mode::Step
+        };
+    }
+
+    /// Decides whether to stop before the instruction at the emulator's pc executes.
+    pub fn check(&mut self, chip8: &Chip8) -> Option<StopReason> {
+        if core::mem::take(&mut self.resuming) {
+            return None;
+        }
+        let reason = self.stop_reason(chip8);
+        if reason.is_some() {
+            self.mode = Mode::Run;
+            self.resuming = true;
+        }
+        reason
+    }
+
+    fn stop_reason(&self, chip8: &Chip8) -> Option<StopReason> {
+        let pc = chip8.pc;
+        match self.mode {
+            Mode::Step => return Some(StopReason::Step { pc }),
+            Mode::StepOver {
+                pc: return_pc,
+                depth,
+            } if pc == return_pc && chip8.stack().len() == depth => {
+                return Some(StopReason::Step { pc })
+            }
+            _ => {}
+        }
+        if self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint { pc });
+        }
+        let opcode = chip8.read_u16(pc);
+        if let Some(&pattern) = self.opcode_breakpoints.iter().find(|p| p.matches(opcode)) {
+            return Some(StopReason::OpcodeBreakpoint {
+                pc,
+                opcode,
+                pattern,
+            });
+        }
+        if opcode & 0xF000 == 0x2000 && chip8.stack.is_full() {
+            return Some(StopReason::StackOverflow { pc, opcode });
+        }
+        if opcode == 0x00EE && chip8.stack().is_empty() {
+            return Some(StopReason::StackUnderflow { pc });
+        }
+        let (access, accessed) = memory_access(chip8)?;
+        self.watchpoints
+            .iter()
+            .filter(|watchpoint| watchpoint.access.includes(access))
+            .find_map(|watchpoint| {
+                let start = accessed.start.max(*watchpoint.range.start() as usize);
+                let end = accessed.end.min(*watchpoint.range.end() as usize + 1);
+                (start < end).then_some(StopReason::Watchpoint {
+                    pc,
+                    opcode,
+                    addr: start as u16,
+                    access,
+                })
+            })
+    }
+
+    /// Executes up to `max_instructions`, stopping early at a breakpoint, watchpoint or error.
+    pub fn run(
+        &mut self,
+        chip8: &mut Chip8,
+        pressed_keys: [bool; 16],
+        max_instructions: usize,
+    ) -> Result<Option<StopReason>, Chip8Error> {
+        for _ in 0..max_instructions {
+            if let Some(reason) = self.check(chip8) {
+                return Ok(Some(reason));
+            }
+            chip8.step(pressed_keys)?;
+        }
+        Ok(None)
+    }
+}
+
+/// The memory the instruction at pc will access through I, if any
+fn memory_access(chip8: &Chip8) -> Option<(Access, Range<usize>)> {
+    let opcode = chip8.read_u16(chip8.pc);
+    let x = ((opcode >> 8) & 0xF) as usize;
+    let y = ((opcode >> 4) & 0xF) as usize;
+    let n = (opcode & 0xF) as usize;
+    let (access, len) = match opcode & 0xF00F {
+        0x5002 => (Access::Write, x.abs_diff(y) + 1),
+        0x5003 => (Access::Read, x.abs_diff(y) + 1),
+        _ if opcode & 0xF000 == 0xD000 => {
+            let sprite_len = if n == 0 { 32 } else { n };
+            (
+                Access::Read,
+                sprite_len * chip8.selected_planes.count_ones() as usize,
+            )
+        }
+        _ => match opcode & 0xF0FF {
+            0xF002 if x == 0 => (Access::Read, 16),
+            0xF033 => (Access::Write, 3),
+            0xF055 => (Access::Write, x + 1),
+            0xF065 => (Access::Read, x + 1),
+            _ => return None,
+        },
+    };
+    let start = chip8.index_reg as usize;
+    Some((access, start..(start + len).min(MEMORY_SIZE)))
+}
+
+/// A summary of the registers, timers and stack, one item per line
+pub fn format_state(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    let opcode = chip8.read_u16(chip8.pc);
+    let inst = Instruction::decode(opcode, chip8.read_u16(chip8.pc.wrapping_add(2)));
+    writeln!(out, "PC {:04X}  opcode {opcode:04X}  {inst}", chip8.pc).unwrap();
+    writeln!(
+        out,
+        "I  {:04X}  DT {:02X}  ST {:02X}",
+        chip8.index_reg, chip8.delay_timer, chip8.sound_timer
+    )
+    .unwrap();
+    for (row, registers) in chip8.registers.chunks(8).enumerate() {
+        for (col, value) in registers.iter().enumerate() {
+            write!(out, "V{:X} {value:02X}  ", row * 8 + col).unwrap();
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+    }
+    write!(out, "stack").unwrap();
+    if chip8.stack().is_empty() {
+        write!(out, " (empty)").unwrap();
+    }
+    for addr in chip8.stack().iter().rev() {
+        write!(out, " {addr:04X}").unwrap();
+    }
+    out.push('\n');
+    out
+}
+
+/// A hex dump of memory, 16 bytes per line
+pub fn format_memory(chip8: &Chip8, range: Range<usize>) -> String {
+    let range = range.start.min(MEMORY_SIZE)..range.end.min(MEMORY_SIZE);
+    let mut out = String::new();
+    for line_start in range.clone().step_by(16) {
+        let line_end = (line_start + 16).min(range.end);
+        write!(out, "{line_start:04X}:").unwrap();
+        for byte in &chip8.memory[line_start..line_end] {
+            write!(out, " {byte:02X}").unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn test_opcode_pattern() {
+    let draw: OpcodePattern = "DXYN".parse().unwrap();
+    assert!(draw.matches(0xD125));
+    assert!(!draw.matches(0xC125));
+    let store: OpcodePattern = "fx55".parse().unwrap();
+    assert!(store.matches(0xF355));
+    assert!(!store.matches(0xF365));
+    assert_eq!(store.to_string(), "FX55");
+    assert!("DXY".parse::<OpcodePattern>().is_err());
+    assert!("DXYZ".parse::<OpcodePattern>().is_err());
+}
+
+#[test]
+fn test_debugger() {
+    use crate::Quirks;
+
+    // (0x200) v0 := 1, call 0x20A, i := 0x300, save v0, jump 0x208
+    // (0x20A) v1 := 2, return
+    let program = [
+        0x60, 0x01, 0x22, 0x0A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x08, 0x61, 0x02, 0x00, 0xEE,
+    ];
+    let mut chip8 = Chip8::load_program(&program, Quirks::default()).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x202);
+    debugger.add_watchpoint(Watchpoint {
+        range: 0x300..=0x30F,
+        access: Access::Write,
+    });
+    let keys = [false; 16];
+
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::Breakpoint { pc: 0x202 }))
+    );
+    debugger.step_over(&chip8);
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::Step { pc: 0x204 }))
+    );
+    assert_eq!(chip8.registers[1], 2);
+    debugger.resume();
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::Watchpoint {
+            pc: 0x206,
+            opcode: 0xF055,
+            addr: 0x300,
+            access: Access::Write
+        }))
+    );
+    debugger.step();
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::Step { pc: 0x208 }))
+    );
+    debugger.add_opcode_breakpoint("1NNN".parse().unwrap());
+    debugger.resume();
+    assert!(matches!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::OpcodeBreakpoint { pc: 0x208, .. }))
+    ));
+    debugger.clear();
+    debugger.resume();
+    assert_eq!(debugger.run(&mut chip8, keys, 100), Ok(None));
+
+    // returning with nothing to return to stops before the error, then resuming runs into it
+    chip8.set_pc(0x20C);
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Ok(Some(StopReason::StackUnderflow { pc: 0x20C }))
+    );
+    assert_eq!(
+        debugger.run(&mut chip8, keys, 100),
+        Err(Chip8Error::StackUnderflow {
+            pc: 0x20C,
+            opcode: 0x00EE
+        })
+    );
+}
@@ -0,0 +1,630 @@
+//! Decoding of CHIP-8, SUPER-CHIP and XO-CHIP instructions, and disassembly of whole programs.
+
+#[cfg(feature = "alloc")]
+use alloc::{collections::BTreeMap, format, string::String, string::ToString, vec, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::fmt::Write;
+#[cfg(feature = "alloc")]
+use core::str::FromStr;
+
+#[cfg(feature = "alloc")]
+use crate::PROGRAM_START;
+
+/// A decoded instruction. `x` and `y` are register numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    Clear,
+    /// 00EE
+    Return,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    Lores,
+    /// 00FF
+    Hires,
+    /// 0NNN, a call to a machine code routine of the host interpreter
+    MachineCall(u16),
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN, skip the next instruction if vx == value
+    SkipIfEqual { x: u8, value: u8 },
+    /// 4XNN, skip the next instruction if vx != value
+    SkipIfNotEqual { x: u8, value: u8 },
+    /// 5XY0
+    SkipIfRegistersEqual { x: u8, y: u8 },
+    /// 5XY2, store vx..=vy at I
+    StoreRange { x: u8, y: u8 },
+    /// 5XY3, load vx..=vy from I
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    Set { x: u8, value: u8 },
+    /// 7XNN
+    Add { x: u8, value: u8 },
+    /// 8XY0
+    Copy { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddRegister { x: u8, y: u8 },
+    /// 8XY5, vx -= vy
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7, vx = vy - vx
+    SubReversed { x: u8, y: u8 },
+    /// 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0
+    SkipIfRegistersNotEqual { x: u8, y: u8 },
+    /// ANNN
+    SetIndex(u16),
+    /// BNNN
+    JumpOffset(u16),
+    /// CXNN
+    Random { x: u8, mask: u8 },
+    /// DXYN
+    Draw { x: u8, y: u8, height: u8 },
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// F000 NNNN, the only instruction that is 4 bytes long
+    SetIndexLong(u16),
+    /// FN01
+    SelectPlanes(u8),
+    /// F002
+    LoadAudio,
+    /// FX07
+    GetDelay(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddIndex(u8),
+    /// FX29
+    SmallFont(u8),
+    /// FX30
+    BigFont(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX3A
+    SetPitch(u8),
+    /// FX55
+    Store(u8),
+    /// FX65
+    Load(u8),
+    /// FX75
+    SaveFlags(u8),
+    /// FX85
+    LoadFlags(u8),
+    /// Not an instruction, most likely data
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decodes an opcode. `next` is the word that follows it, which is only used by F000 NNNN.
+    pub fn decode(opcode: u16, next: u16) -> Instruction {
+        use Instruction::*;
+        let x = ((opcode >> 8) & 0xF) as u8;
+        let y = ((opcode >> 4) & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        match (opcode >> 12, x, y, n) {
+            (0x0, 0x0, 0xC, _) => ScrollDown(n),
+            (0x0, 0x0, 0xD, _) => ScrollUp(n),
+            (0x0, 0x0, 0xE, 0x0) => Clear,
+            (0x0, 0x0, 0xE, 0xE) => Return,
+            (0x0, 0x0, 0xF, 0xB) => ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Exit,
+            (0x0, 0x0, 0xF, 0xE) => Lores,
+            (0x0, 0x0, 0xF, 0xF) => Hires,
+            // 0000 is far more likely to be padding than a call to address 0
+            (0x0, _, _, _) if nnn != 0 => MachineCall(nnn),
+            (0x1, _, _, _) => Jump(nnn),
+            (0x2, _, _, _) => Call(nnn),
+            (0x3, _, _, _) => SkipIfEqual { x, value: nn },
+            (0x4, _, _, _) => SkipIfNotEqual { x, value: nn },
+            (0x5, _, _, 0x0) => SkipIfRegistersEqual { x, y },
+            (0x5, _, _, 0x2) => StoreRange { x, y },
+            (0x5, _, _, 0x3) => LoadRange { x, y },
+            (0x6, _, _, _) => Set { x, value: nn },
+            (0x7, _, _, _) => Add { x, value: nn },
+            (0x8, _, _, 0x0) => Copy { x, y },
+            (0x8, _, _, 0x1) => Or { x, y },
+            (0x8, _, _, 0x2) => And { x, y },
+            (0x8, _, _, 0x3) => Xor { x, y },
+            (0x8, _, _, 0x4) => AddRegister { x, y },
+            (0x8, _, _, 0x5) => Sub { x, y },
+            (0x8, _, _, 0x6) => ShiftRight { x, y },
+            (0x8, _, _, 0x7) => SubReversed { x, y },
+            (0x8, _, _, 0xE) => ShiftLeft { x, y },
+            (0x9, _, _, 0x0) => SkipIfRegistersNotEqual { x, y },
+            (0xA, _, _, _) => SetIndex(nnn),
+            (0xB, _, _, _) => JumpOffset(nnn),
+            (0xC, _, _, _) => Random { x, mask: nn },
+            (0xD, _, _, _) => Draw { x, y, height: n },
+            (0xE, _, 0x9, 0xE) => SkipIfKey(x),
+            (0xE, _, 0xA, 0x1) => SkipIfNotKey(x),
+            (0xF, 0x0, 0x0, 0x0) => SetIndexLong(next),
+            (0xF, _, 0x0, 0x1) => SelectPlanes(x),
+            (0xF, 0x0, 0x0, 0x2) => LoadAudio,
+            (0xF, _, 0x0, 0x7) => GetDelay(x),
+            (0xF, _, 0x0, 0xA) => WaitKey(x),
+            (0xF, _, 0x1, 0x5) => SetDelay(x),
+            (0xF, _, 0x1, 0x8) => SetSound(x),
+            (0xF, _, 0x1, 0xE) => AddIndex(x),
+            (0xF, _, 0x2, 0x9) => SmallFont(x),
+            (0xF, _, 0x3, 0x0) => BigFont(x),
+            (0xF, _, 0x3, 0x3) => Bcd(x),
+            (0xF, _, 0x3, 0xA) => SetPitch(x),
+            (0xF, _, 0x5, 0x5) => Store(x),
+            (0xF, _, 0x6, 0x5) => Load(x),
+            (0xF, _, 0x7, 0x5) => SaveFlags(x),
+            (0xF, _, 0x8, 0x5) => LoadFlags(x),
+            _ => Unknown(opcode),
+        }
+    }
+
+    /// Decodes the instruction at the start of `bytes`, or returns None if `bytes` ends partway through it.
+    pub fn decode_bytes(bytes: &[u8]) -> Option<Instruction> {
+        let word = |idx: usize| Some(u16::from_be_bytes([*bytes.get(idx)?, *bytes.get(idx + 1)?]));
+        let opcode = word(0)?;
+        if opcode == 0xF000 {
+            Some(Instruction::SetIndexLong(word(2)?))
+        } else {
+            Some(Instruction::decode(opcode, 0))
+        }
+    }
+
+    /// The number of bytes the instruction takes up in memory
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::SetIndexLong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the instruction conditionally skips the one after it
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            SkipIfEqual { .. }
+                | SkipIfNotEqual { .. }
+                | SkipIfRegistersEqual { .. }
+                | SkipIfRegistersNotEqual { .. }
+                | SkipIfKey(_)
+                | SkipIfNotKey(_)
+        )
+    }
+
+    /// Writes the instruction in the given syntax, naming any address that has a label.
+    #[cfg(feature = "alloc")]
+    pub fn format(&self, syntax: Syntax, labels: &BTreeMap<u16, String>) -> String {
+        let addr = |addr: &u16| match labels.get(addr) {
+            Some(label) => label.clone(),
+            None => format!("{addr:#05x}"),
+        };
+        match syntax {
+            Syntax::Octo => self.format_octo(addr),
+            Syntax::Classic => self.format_classic(addr),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn format_octo(&self, addr: impl Fn(&u16) -> String) -> String {
+        use Instruction::*;
+        match self {
+            ScrollDown(n) => format!("scroll-down {n}"),
+            ScrollUp(n) => format!("scroll-up {n}"),
+            Clear => "clear".to_string(),
+            Return => "return".to_string(),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Lores => "lores".to_string(),
+            Hires => "hires".to_string(),
+            // Octo has no syntax for machine code calls, so they're written out as bytes
+            MachineCall(nnn) => format!("{:#04x} {:#04x}", nnn >> 8, nnn & 0xFF),
+            Jump(nnn) => format!("jump {}", addr(nnn)),
+            Call(nnn) => format!(":call {}", addr(nnn)),
+            // Octo's conditions say when the next instruction runs, rather than when it's skipped
+            SkipIfEqual { x, value } => format!("if v{x:x} != {value:#04x} then"),
+            SkipIfNotEqual { x, value } => format!("if v{x:x} == {value:#04x} then"),
+            SkipIfRegistersEqual { x, y } => format!("if v{x:x} != v{y:x} then"),
+            StoreRange { x, y } => format!("save v{x:x} - v{y:x}"),
+            LoadRange { x, y } => format!("load v{x:x} - v{y:x}"),
+            Set { x, value } => format!("v{x:x} := {value:#04x}"),
+            Add { x, value } => format!("v{x:x} += {value:#04x}"),
+            Copy { x, y } => format!("v{x:x} := v{y:x}"),
+            Or { x, y } => format!("v{x:x} |= v{y:x}"),
+            And { x, y } => format!("v{x:x} &= v{y:x}"),
+            Xor { x, y } => format!("v{x:x} ^= v{y:x}"),
+            AddRegister { x, y } => format!("v{x:x} += v{y:x}"),
+            Sub { x, y } => format!("v{x:x} -= v{y:x}"),
+            ShiftRight { x, y } => format!("v{x:x} >>= v{y:x}"),
+            SubReversed { x, y } => format!("v{x:x} =- v{y:x}"),
+            ShiftLeft { x, y } => format!("v{x:x} <<= v{y:x}"),
+            SkipIfRegistersNotEqual { x, y } => format!("if v{x:x} == v{y:x} then"),
+            SetIndex(nnn) => format!("i := {}", addr(nnn)),
+            JumpOffset(nnn) => format!("jump0 {}", addr(nnn)),
+            Random { x, mask } => format!("v{x:x} := random {mask:#04x}"),
+            Draw { x, y, height } => format!("sprite v{x:x} v{y:x} {height}"),
+            SkipIfKey(x) => format!("if v{x:x} -key then"),
+            SkipIfNotKey(x) => format!("if v{x:x} key then"),
+            SetIndexLong(nnnn) => match addr(nnnn) {
+                label if label.starts_with("0x") => format!("i := long {nnnn:#06x}"),
+                label => format!("i := long {label}"),
+            },
+            SelectPlanes(n) => format!("plane {n}"),
+            LoadAudio => "audio".to_string(),
+            GetDelay(x) => format!("v{x:x} := delay"),
+            WaitKey(x) => format!("v{x:x} := key"),
+            SetDelay(x) => format!("delay := v{x:x}"),
+            SetSound(x) => format!("buzzer := v{x:x}"),
+            AddIndex(x) => format!("i += v{x:x}"),
+            SmallFont(x) => format!("i := hex v{x:x}"),
+            BigFont(x) => format!("i := bighex v{x:x}"),
+            Bcd(x) => format!("bcd v{x:x}"),
+            SetPitch(x) => format!("pitch := v{x:x}"),
+            Store(x) => format!("save v{x:x}"),
+            Load(x) => format!("load v{x:x}"),
+            SaveFlags(x) => format!("saveflags v{x:x}"),
+            LoadFlags(x) => format!("loadflags v{x:x}"),
+            Unknown(opcode) => format!("{:#04x} {:#04x}", opcode >> 8, opcode & 0xFF),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn format_classic(&self, addr: impl Fn(&u16) -> String) -> String {
+        use Instruction::*;
+        match self {
+            ScrollDown(n) => format!("SCD {n}"),
+            ScrollUp(n) => format!("SCU {n}"),
+            Clear => "CLS".to_string(),
+            Return => "RET".to_string(),
+            ScrollRight => "SCR".to_string(),
+            ScrollLeft => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            Lores => "LOW".to_string(),
+            Hires => "HIGH".to_string(),
+            MachineCall(nnn) => format!("SYS {}", addr(nnn)),
+            Jump(nnn) => format!("JP {}", addr(nnn)),
+            Call(nnn) => format!("CALL {}", addr(nnn)),
+            SkipIfEqual { x, value } => format!("SE V{x:X}, {value:#04x}"),
+            SkipIfNotEqual { x, value } => format!("SNE V{x:X}, {value:#04x}"),
+            SkipIfRegistersEqual { x, y } => format!("SE V{x:X}, V{y:X}"),
+            StoreRange { x, y } => format!("LD [I], V{x:X}-V{y:X}"),
+            LoadRange { x, y } => format!("LD V{x:X}-V{y:X}, [I]"),
+            Set { x, value } => format!("LD V{x:X}, {value:#04x}"),
+            Add { x, value } => format!("ADD V{x:X}, {value:#04x}"),
+            Copy { x, y } => format!("LD V{x:X}, V{y:X}"),
+            Or { x, y } => format!("OR V{x:X}, V{y:X}"),
+            And { x, y } => format!("AND V{x:X}, V{y:X}"),
+            Xor { x, y } => format!("XOR V{x:X}, V{y:X}"),
+            AddRegister { x, y } => format!("ADD V{x:X}, V{y:X}"),
+            Sub { x, y } => format!("SUB V{x:X}, V{y:X}"),
+            ShiftRight { x, y } => format!("SHR V{x:X}, V{y:X}"),
+            SubReversed { x, y } => format!("SUBN V{x:X}, V{y:X}"),
+            ShiftLeft { x, y } => format!("SHL V{x:X}, V{y:X}"),
+            SkipIfRegistersNotEqual { x, y } => format!("SNE V{x:X}, V{y:X}"),
+            SetIndex(nnn) => format!("LD I, {}", addr(nnn)),
+            JumpOffset(nnn) => format!("JP V0, {}", addr(nnn)),
+            Random { x, mask } => format!("RND V{x:X}, {mask:#04x}"),
+            Draw { x, y, height } => format!("DRW V{x:X}, V{y:X}, {height}"),
+            SkipIfKey(x) => format!("SKP V{x:X}"),
+            SkipIfNotKey(x) => format!("SKNP V{x:X}"),
+            SetIndexLong(nnnn) => match addr(nnnn) {
+                label if label.starts_with("0x") => format!("LD I, LONG {nnnn:#06x}"),
+                label => format!("LD I, LONG {label}"),
+            },
+            SelectPlanes(n) => format!("PLANE {n}"),
+            LoadAudio => "AUDIO".to_string(),
+            GetDelay(x) => format!("LD V{x:X}, DT"),
+            WaitKey(x) => format!("LD V{x:X}, K"),
+            SetDelay(x) => format!("LD DT, V{x:X}"),
+            SetSound(x) => format!("LD ST, V{x:X}"),
+            AddIndex(x) => format!("ADD I, V{x:X}"),
+            SmallFont(x) => format!("LD F, V{x:X}"),
+            BigFont(x) => format!("LD HF, V{x:X}"),
+            Bcd(x) => format!("LD B, V{x:X}"),
+            SetPitch(x) => format!("LD PITCH, V{x:X}"),
+            Store(x) => format!("LD [I], V{x:X}"),
+            Load(x) => format!("LD V{x:X}, [I]"),
+            SaveFlags(x) => format!("LD R, V{x:X}"),
+            LoadFlags(x) => format!("LD V{x:X}, R"),
+            Unknown(opcode) => format!("DW {opcode:#06x}"),
+        }
+    }
+}
+
+/// Classic mnemonics, without labels
+#[cfg(feature = "alloc")]
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Syntax::Classic, &BTreeMap::new()))
+    }
+}
+
+/// Assembly language to write instructions in
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Octo's syntax, e.g. `v0 += v1`. A disassembly in this syntax can be assembled again.
+    #[default]
+    Octo,
+    /// The mnemonics from Cowgod's technical reference, e.g. `ADD V0, V1`
+    Classic,
+}
+
+#[cfg(feature = "alloc")]
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "octo" => Ok(Syntax::Octo),
+            "classic" => Ok(Syntax::Classic),
+            _ => Err(format!("unknown syntax {s:?}, expected octo or classic")),
+        }
+    }
+}
+
+/// A program split into code and data.
+///
+/// Code is found by following every path of execution from the entry point:
+/// jumps, calls and both outcomes of each skip. Everything else is treated as data.
+/// Computed jumps (BNNN) can't be followed, so code only reached through them shows up as data.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    /// the address the program is loaded at
+    origin: u16,
+    bytes: Vec<u8>,
+    /// every instruction reachable from the entry point, by address
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, String>,
+}
+
+#[cfg(feature = "alloc")]
+impl Disassembly {
+    /// Disassembles a program loaded at the usual 0x200, starting execution there.
+    pub fn new(program: &[u8]) -> Self {
+        Self::with_origin(program, PROGRAM_START)
+    }
+
+    /// Disassembles a program that is loaded at, and starts executing from, `origin`.
+    pub fn with_origin(program: &[u8], origin: u16) -> Self {
+        let mut disassembly = Disassembly {
+            origin,
+            bytes: program.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.labels.insert(origin, "main".to_string());
+        let mut pending = vec![origin];
+        while let Some(addr) = pending.pop() {
+            if disassembly.instructions.contains_key(&addr) {
+                continue;
+            }
+            let Some(inst) = disassembly.decode_at(addr) else {
+                continue;
+            };
+            disassembly.instructions.insert(addr, inst);
+            let next = addr.wrapping_add(inst.size());
+            match inst {
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::Jump(target) => {
+                    disassembly.add_label(target, "label");
+                    pending.push(target);
+                }
+                Instruction::Call(target) => {
+                    disassembly.add_label(target, "sub");
+                    pending.extend([target, next]);
+                }
+                // the target depends on v0, so only label the base of the jump table
+                Instruction::JumpOffset(target) => disassembly.add_label(target, "table"),
+                Instruction::SetIndex(target) | Instruction::SetIndexLong(target) => {
+                    disassembly.add_label(target, "data");
+                    pending.push(next);
+                }
+                inst if inst.is_skip() => {
+                    pending.push(next);
+                    // a skip jumps over the 4 byte F000 NNNN as a whole
+                    let skipped_size = disassembly.decode_at(next).map_or(2, |next| next.size());
+                    pending.push(next.wrapping_add(skipped_size));
+                }
+                _ => pending.push(next),
+            }
+        }
+        disassembly
+    }
+
+    /// Decodes the instruction at `addr`, if it lies within the program and isn't data.
+    fn decode_at(&self, addr: u16) -> Option<Instruction> {
+        let offset = addr.checked_sub(self.origin)? as usize;
+        match Instruction::decode_bytes(self.bytes.get(offset..)?)? {
+            Instruction::Unknown(_) => None,
+            inst => Some(inst),
+        }
+    }
+
+    /// Names an address within the program, unless it already has a name
+    fn add_label(&mut self, addr: u16, prefix: &str) {
+        if self.contains(addr) {
+            self.labels
+                .entry(addr)
+                .or_insert_with(|| format!("{prefix}_{addr:04x}"));
+        }
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        (self.origin as usize..self.origin as usize + self.bytes.len()).contains(&(addr as usize))
+    }
+
+    /// Every instruction reachable from the entry point, by address
+    pub fn instructions(&self) -> &BTreeMap<u16, Instruction> {
+        &self.instructions
+    }
+
+    /// Names for the targets of jumps, calls and I, by address
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    /// Writes out the whole program.
+    ///
+    /// Octo output is source code that assembles back into the same bytes.
+    /// Classic output is a listing with the address and bytes of every line.
+    pub fn format(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+        if syntax == Syntax::Octo && self.origin != PROGRAM_START {
+            writeln!(out, ":org {:#06x}", self.origin).unwrap();
+        }
+        let end = self.origin as usize + self.bytes.len();
+        let mut addr = self.origin as usize;
+        while addr < end {
+            if let Some(label) = self.labels.get(&(addr as u16)) {
+                match syntax {
+                    Syntax::Octo => writeln!(out, ": {label}").unwrap(),
+                    Syntax::Classic => writeln!(out, "{label}:").unwrap(),
+                }
+            }
+            let offset = addr - self.origin as usize;
+            // an instruction that overlaps a label, e.g. one jumped into the middle of, is written as data
+            let inst = self.instructions.get(&(addr as u16)).filter(|inst| {
+                (addr + 1..addr + inst.size() as usize)
+                    .all(|addr| !self.labels.contains_key(&(addr as u16)))
+            });
+            let len = match inst {
+                Some(inst) => inst.size() as usize,
+                None => {
+                    // data runs up to the next line of code or label, 8 bytes per line at most
+                    (addr + 1..end.min(addr + 8))
+                        .find(|&addr| {
+                            self.instructions.contains_key(&(addr as u16))
+                                || self.labels.contains_key(&(addr as u16))
+                        })
+                        .unwrap_or(end.min(addr + 8))
+                        - addr
+                }
+            };
+            let bytes = &self.bytes[offset..offset + len];
+            let text = match inst {
+                Some(inst) => inst.format(syntax, &self.labels),
+                None => {
+                    let bytes = bytes.iter().map(|byte| format!("{byte:#04x}"));
+                    match syntax {
+                        Syntax::Octo => bytes.collect::<Vec<_>>().join(" "),
+                        Syntax::Classic => format!("DB {}", bytes.collect::<Vec<_>>().join(", ")),
+                    }
+                }
+            };
+            match syntax {
+                Syntax::Octo => writeln!(out, "\t{text}").unwrap(),
+                Syntax::Classic => {
+                    let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                    writeln!(out, "{addr:04X}  {hex:<16}  {text}").unwrap()
+                }
+            }
+            addr += len;
+        }
+        out
+    }
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(Instruction::decode(0x00E0, 0), Instruction::Clear);
+    assert_eq!(
+        Instruction::decode(0xD125, 0),
+        Instruction::Draw {
+            x: 1,
+            y: 2,
+            height: 5
+        }
+    );
+    assert_eq!(
+        Instruction::decode(0x8AB6, 0),
+        Instruction::ShiftRight { x: 0xA, y: 0xB }
+    );
+    assert_eq!(Instruction::decode(0x0000, 0), Instruction::Unknown(0x0000));
+    assert_eq!(Instruction::decode(0x8008, 0), Instruction::Unknown(0x8008));
+    assert_eq!(
+        Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34]),
+        Some(Instruction::SetIndexLong(0x1234))
+    );
+    assert_eq!(Instruction::decode_bytes(&[0xF0, 0x00, 0x12]), None);
+
+    let inst = Instruction::SkipIfEqual { x: 3, value: 7 };
+    assert_eq!(inst.to_string(), "SE V3, 0x07");
+    assert_eq!(
+        inst.format(Syntax::Octo, &BTreeMap::new()),
+        "if v3 != 0x07 then"
+    );
+}
+
+#[test]
+fn test_disassemble() {
+    #[rustfmt::skip]
+    let program = [
+        0x00, 0xE0, // 0x200: clear
+        0xA2, 0x0C, // 0x202: i := 0x20C
+        0x22, 0x08, // 0x204: call 0x208
+        0x12, 0x06, // 0x206: jump 0x206
+        0xD0, 0x01, // 0x208: sprite v0 v0 1
+        0x00, 0xEE, // 0x20A: return
+        0xFF, 0x81, // 0x20C: sprite data
+    ];
+    let disassembly = Disassembly::new(&program);
+    assert_eq!(
+        disassembly
+            .instructions()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]
+    );
+    assert_eq!(
+        disassembly.format(Syntax::Octo),
+        "\
+: main
+\tclear
+\ti := data_020c
+\t:call sub_0208
+: label_0206
+\tjump label_0206
+: sub_0208
+\tsprite v0 v0 1
+\treturn
+: data_020c
+\t0xff 0x81
+"
+    );
+    assert_eq!(
+        disassembly.format(Syntax::Classic).lines().nth(2),
+        Some("0202  A20C              LD I, data_020c")
+    );
+}
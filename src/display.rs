@@ -0,0 +1,179 @@
+/// Dimensions of the SUPER-CHIP high resolution mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// Dimensions of the original CHIP-8 display
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+/// XO-CHIP draws to two bitplanes, which combine into 4 colors
+pub const PLANE_COUNT: usize = 2;
+
+/// A framebuffer that can switch between 64x32 and 128x64 pixels.
+/// Each pixel is a bitmask of the planes that are set at that position,
+/// so its value is a color index from 0 to 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Display {
+    /// indexed as `[row][col]` or `[y][x]`
+    /// In low resolution mode only the top-left 64x32 pixels are used.
+    pub(crate) pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    pub(crate) hires: bool,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            pixels: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+        }
+    }
+}
+
+impl Display {
+    /// Width in pixels of the current resolution
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    /// Height in pixels of the current resolution
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// The color index of the pixel at (x, y)
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y][x] = color;
+    }
+
+    /// The visible rows of the current resolution, top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let width = self.width();
+        self.pixels[..self.height()]
+            .iter()
+            .map(move |row| &row[..width])
+    }
+
+    /// Clears the given planes
+    pub(crate) fn clear(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut().flatten() {
+            *pixel &= !planes;
+        }
+    }
+
+    /// Switching resolution clears the screen
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear(u8::MAX);
+    }
+
+    /// Flips the given plane of the pixel at (x, y) and returns true if it was turned off
+    pub(crate) fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y][x];
+        *pixel ^= plane;
+        *pixel & plane == 0
+    }
+
+    /// Moves the given planes down by n pixels
+    pub(crate) fn scroll_down(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let shifted = if y >= n { self.pixels[y - n][x] } else { 0 };
+                self.pixels[y][x] = (self.pixels[y][x] & !planes) | (shifted & planes);
+            }
+        }
+    }
+
+    /// Moves the given planes up by n pixels
+    pub(crate) fn scroll_up(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in 0..width {
+                let shifted = if y + n < height {
+                    self.pixels[y + n][x]
+                } else {
+                    0
+                };
+                self.pixels[y][x] = (self.pixels[y][x] & !planes) | (shifted & planes);
+            }
+        }
+    }
+
+    /// Moves the given planes right by n pixels
+    pub(crate) fn scroll_right(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for row in &mut self.pixels[..height] {
+            for x in (0..width).rev() {
+                let shifted = if x >= n { row[x - n] } else { 0 };
+                row[x] = (row[x] & !planes) | (shifted & planes);
+            }
+        }
+    }
+
+    /// Moves the given planes left by n pixels
+    pub(crate) fn scroll_left(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for row in &mut self.pixels[..height] {
+            for x in 0..width {
+                let shifted = if x + n < width { row[x + n] } else { 0 };
+                row[x] = (row[x] & !planes) | (shifted & planes);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_scroll() {
+    let mut display = Display::default();
+    display.set(0, 0, 1);
+    display.set(63, 31, 1);
+    display.scroll_down(1, 2);
+    assert_eq!(display.get(0, 2), 1);
+    assert_eq!(display.get(0, 0), 0);
+    // scrolled off the bottom of the low resolution screen
+    assert_eq!(display.get(63, 31), 0);
+    display.scroll_right(1, 4);
+    assert_eq!(display.get(4, 2), 1);
+    display.scroll_up(1, 2);
+    display.scroll_left(1, 4);
+    assert_eq!(display.get(0, 0), 1);
+    display.scroll_left(1, 4);
+    assert_eq!(
+        display
+            .rows()
+            .flatten()
+            .filter(|&&pixel| pixel != 0)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_planes() {
+    let mut display = Display::default();
+    display.set(0, 0, 0b11);
+    // only the selected plane moves
+    display.scroll_right(0b10, 1);
+    assert_eq!(display.get(0, 0), 0b01);
+    assert_eq!(display.get(1, 0), 0b10);
+    assert!(display.toggle(1, 0, 0b10));
+    assert!(!display.toggle(1, 0, 0b01));
+    display.clear(0b01);
+    assert_eq!(display.get(0, 0), 0);
+    assert_eq!(display.get(1, 0), 0);
+}
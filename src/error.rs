@@ -0,0 +1,109 @@
+use core::fmt;
+
+/// Reasons the emulator can't continue running a program.
+/// Errors raised while executing an instruction carry the address and opcode of that instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode doesn't correspond to any supported instruction
+    InvalidInstruction { pc: u16, opcode: u16 },
+    /// 00EE was executed without a return address on the stack
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// 2NNN was executed with the stack full
+    StackOverflow { pc: u16, opcode: u16 },
+    /// 0NNN called a machine code routine, which stops the program with [`crate::SysCallPolicy::Trap`]
+    MachineCall { pc: u16, opcode: u16 },
+    /// FX29 or FX30 was executed with a value in VX that isn't a hexadecimal digit
+    InvalidFontCharacter { pc: u16, opcode: u16, value: u8 },
+    /// The instruction accesses memory past the end of the address space through I
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    /// The program doesn't fit in memory
+    ProgramTooLarge { size: usize, max_size: usize },
+    /// A program segment or the entry point is below `reserved_end`, in the interpreter's memory
+    ReservedMemory { addr: u16, reserved_end: u16 },
+    /// The fonts overlap a program segment at `addr`, or don't fit in memory
+    FontsOverlap { addr: u16 },
+    /// Two program segments both include `addr`
+    OverlappingSegments { addr: u16 },
+}
+
+impl Chip8Error {
+    /// The address of the instruction that failed
+    pub fn pc(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::InvalidInstruction { pc, .. }
+            | Chip8Error::StackUnderflow { pc, .. }
+            | Chip8Error::StackOverflow { pc, .. }
+            | Chip8Error::MachineCall { pc, .. }
+            | Chip8Error::InvalidFontCharacter { pc, .. }
+            | Chip8Error::MemoryOutOfBounds { pc, .. } => Some(pc),
+            Chip8Error::ProgramTooLarge { .. }
+            | Chip8Error::ReservedMemory { .. }
+            | Chip8Error::OverlappingSegments { .. }
+            | Chip8Error::FontsOverlap { .. } => None,
+        }
+    }
+
+    /// The opcode of the instruction that failed
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::InvalidInstruction { opcode, .. }
+            | Chip8Error::StackUnderflow { opcode, .. }
+            | Chip8Error::StackOverflow { opcode, .. }
+            | Chip8Error::MachineCall { opcode, .. }
+            | Chip8Error::InvalidFontCharacter { opcode, .. }
+            | Chip8Error::MemoryOutOfBounds { opcode, .. } => Some(opcode),
+            Chip8Error::ProgramTooLarge { .. }
+            | Chip8Error::ReservedMemory { .. }
+            | Chip8Error::OverlappingSegments { .. }
+            | Chip8Error::FontsOverlap { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidInstruction { pc, opcode } => {
+                write!(f, "invalid instruction {opcode:#06x} at {pc:#06x}")
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => write!(
+                f,
+                "{opcode:#06x} at {pc:#06x} returned from a subroutine with an empty stack"
+            ),
+            Chip8Error::StackOverflow { pc, opcode } => write!(
+                f,
+                "{opcode:#06x} at {pc:#06x} called a subroutine with the stack full"
+            ),
+            Chip8Error::MachineCall { pc, opcode } => write!(
+                f,
+                "{opcode:#06x} at {pc:#06x} called a machine code routine at {:#05x}, which isn't emulated",
+                opcode & 0x0FFF
+            ),
+            Chip8Error::InvalidFontCharacter { pc, opcode, value } => write!(
+                f,
+                "{opcode:#06x} at {pc:#06x} looked up a font character for {value:#04x}, which isn't a hexadecimal digit"
+            ),
+            Chip8Error::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "{opcode:#06x} at {pc:#06x} accessed address {addr:#x}, past the end of memory"
+            ),
+            Chip8Error::ProgramTooLarge { size, max_size } => write!(
+                f,
+                "program is {size} bytes, but at most {max_size} bytes fit in memory"
+            ),
+            Chip8Error::ReservedMemory { addr, reserved_end } => write!(
+                f,
+                "program address {addr:#06x} is in the memory below {reserved_end:#06x} reserved for the interpreter"
+            ),
+            Chip8Error::OverlappingSegments { addr } => {
+                write!(f, "two program segments overlap at {addr:#06x}")
+            }
+            Chip8Error::FontsOverlap { addr } => write!(
+                f,
+                "the fonts overlap the program at {addr:#06x}, or run past the end of memory"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for Chip8Error {}
@@ -1,24 +1,239 @@
-pub struct Font(pub [u8; 5]);
-
-// For some reason, it’s become popular to put it at 050–09F,
-// so you can follow that convention if you want
-const ZERO: Font = Font([0xF0, 0x90, 0x90, 0x90, 0xF0]);
-const ONE: Font = Font([0x20, 0x60, 0x20, 0x20, 0x70]);
-const TWO: Font = Font([0xF0, 0x10, 0xF0, 0x80, 0xF0]);
-const THREE: Font = Font([0xF0, 0x10, 0xF0, 0x10, 0xF0]);
-const FOUR: Font = Font([0x90, 0x90, 0xF0, 0x10, 0x10]);
-const FIVE: Font = Font([0xF0, 0x80, 0xF0, 0x10, 0xF0]);
-const SIX: Font = Font([0xF0, 0x80, 0xF0, 0x90, 0xF0]);
-const SEVEN: Font = Font([0xF0, 0x10, 0x20, 0x40, 0x40]);
-const EIGHT: Font = Font([0xF0, 0x90, 0xF0, 0x90, 0xF0]);
-const NINE: Font = Font([0xF0, 0x90, 0xF0, 0x10, 0xF0]);
-const A: Font = Font([0xF0, 0x90, 0xF0, 0x90, 0x90]);
-const B: Font = Font([0xE0, 0x90, 0xE0, 0x90, 0xE0]);
-const C: Font = Font([0xF0, 0x80, 0x80, 0x80, 0xF0]);
-const D: Font = Font([0xE0, 0x90, 0x90, 0x90, 0xE0]);
-const E: Font = Font([0xF0, 0x80, 0xF0, 0x80, 0xF0]);
-const F: Font = Font([0xF0, 0x80, 0xF0, 0x80, 0x80]);
-
-pub const FONTS: [Font; 16] = [
-    ZERO, ONE, TWO, THREE, FOUR, FIVE, SIX, SEVEN, EIGHT, NINE, A, B, C, D, E, F,
-];
+/// 4x5 hexadecimal digits from 0 to F, used by the FX29 instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmallFont(pub [[u8; 5]; 16]);
+
+/// 8x10 hexadecimal digits from 0 to F, used by the SUPER-CHIP FX30 instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BigFont(pub [[u8; 10]; 16]);
+
+impl SmallFont {
+    /// Bytes of memory the font takes
+    pub const SIZE: usize = 5 * 16;
+
+    /// The font most modern interpreters use, and Octo's default
+    pub const OCTO: SmallFont = SmallFont([
+        [0xF0, 0x90, 0x90, 0x90, 0xF0],
+        [0x20, 0x60, 0x20, 0x20, 0x70],
+        [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+        [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+        [0x90, 0x90, 0xF0, 0x10, 0x10],
+        [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+        [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+        [0xF0, 0x10, 0x20, 0x40, 0x40],
+        [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+        [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+        [0xF0, 0x90, 0xF0, 0x90, 0x90],
+        [0xE0, 0x90, 0xE0, 0x90, 0xE0],
+        [0xF0, 0x80, 0x80, 0x80, 0xF0],
+        [0xE0, 0x90, 0x90, 0x90, 0xE0],
+        [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+        [0xF0, 0x80, 0xF0, 0x80, 0x80],
+    ]);
+
+    /// The original COSMAC VIP interpreter's font
+    pub const VIP: SmallFont = SmallFont([
+        [0xF0, 0x90, 0x90, 0x90, 0xF0],
+        [0x60, 0x20, 0x20, 0x20, 0x70],
+        [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+        [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+        [0xA0, 0xA0, 0xF0, 0x20, 0x20],
+        [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+        [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+        [0xF0, 0x10, 0x10, 0x10, 0x10],
+        [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+        [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+        [0xF0, 0x90, 0xF0, 0x90, 0x90],
+        [0xF0, 0x50, 0x70, 0x50, 0xF0],
+        [0xF0, 0x80, 0x80, 0x80, 0xF0],
+        [0xF0, 0x50, 0x50, 0x50, 0xF0],
+        [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+        [0xF0, 0x80, 0xF0, 0x80, 0x80],
+    ]);
+
+    /// The DREAM 6800's 3 pixel wide font
+    pub const DREAM_6800: SmallFont = SmallFont([
+        [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+        [0x40, 0x40, 0x40, 0x40, 0x40],
+        [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+        [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+        [0x80, 0xA0, 0xA0, 0xE0, 0x20],
+        [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+        [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0x20, 0x20, 0x20, 0x20],
+        [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+        [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+        [0xC0, 0xA0, 0xE0, 0xA0, 0xC0],
+        [0xE0, 0x80, 0x80, 0x80, 0xE0],
+        [0xC0, 0xA0, 0xA0, 0xA0, 0xC0],
+        [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+        [0xE0, 0x80, 0xC0, 0x80, 0x80],
+    ]);
+
+    /// The ETI-660's 3 pixel wide font
+    pub const ETI_660: SmallFont = SmallFont([
+        [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+        [0x20, 0x20, 0x20, 0x20, 0x20],
+        [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+        [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+        [0xA0, 0xA0, 0xE0, 0x20, 0x20],
+        [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+        [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0x20, 0x20, 0x20, 0x20],
+        [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+        [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+        [0x80, 0x80, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0x80, 0x80, 0x80, 0xE0],
+        [0x20, 0x20, 0xE0, 0xA0, 0xE0],
+        [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+        [0xE0, 0x80, 0xC0, 0x80, 0x80],
+    ]);
+
+    /// The names accepted by [`SmallFont::named`]
+    pub const NAMES: [&'static str; 4] = ["octo", "vip", "dream-6800", "eti-660"];
+
+    /// Looks up a built-in font by name
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "octo" => Some(Self::OCTO),
+            "vip" => Some(Self::VIP),
+            "dream-6800" => Some(Self::DREAM_6800),
+            "eti-660" => Some(Self::ETI_660),
+            _ => None,
+        }
+    }
+
+    /// Reads a font from its 80 bytes, 5 for each digit in order, e.g. from a file
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        let mut font = SmallFont([[0; 5]; 16]);
+        for (digit, bytes) in font.0.iter_mut().zip(bytes.chunks(5)) {
+            digit.copy_from_slice(bytes);
+        }
+        Some(font)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_flattened()
+    }
+}
+
+impl BigFont {
+    /// Bytes of memory the font takes
+    pub const SIZE: usize = 10 * 16;
+
+    /// Octo's font, with all 16 digits
+    pub const OCTO: BigFont = BigFont([
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+        [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+        [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+        [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+    ]);
+
+    /// SUPER-CHIP 1.1's rounded digits. It only had 0 to 9, so A to F are Octo's.
+    pub const SCHIP: BigFont = BigFont([
+        [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C],
+        [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C],
+        [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF],
+        [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C],
+        [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C],
+        [0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C],
+        [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60],
+        [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C],
+        [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C],
+        BigFont::OCTO.0[0xA],
+        BigFont::OCTO.0[0xB],
+        BigFont::OCTO.0[0xC],
+        BigFont::OCTO.0[0xD],
+        BigFont::OCTO.0[0xE],
+        BigFont::OCTO.0[0xF],
+    ]);
+
+    /// The names accepted by [`BigFont::named`]
+    pub const NAMES: [&'static str; 2] = ["octo", "schip"];
+
+    /// Looks up a built-in font by name
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "octo" => Some(Self::OCTO),
+            "schip" => Some(Self::SCHIP),
+            _ => None,
+        }
+    }
+
+    /// Reads a font from its 160 bytes, 10 for each digit in order, e.g. from a file
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        let mut font = BigFont([[0; 10]; 16]);
+        for (digit, bytes) in font.0.iter_mut().zip(bytes.chunks(10)) {
+            digit.copy_from_slice(bytes);
+        }
+        Some(font)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_flattened()
+    }
+}
+
+/// The fonts copied into memory when a program is loaded, and where they go.
+///
+/// Start from the default and override parts with struct update syntax:
+/// `Fonts { small: SmallFont::VIP, ..Fonts::default() }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fonts {
+    pub small: SmallFont,
+    pub big: BigFont,
+    /// Where the small font is stored. The big font follows it directly.
+    /// For some reason, it’s become popular to put it at 050–09F,
+    /// so that's the default.
+    pub addr: u16,
+}
+
+impl Fonts {
+    /// Bytes of memory taken by both fonts
+    pub const SIZE: usize = SmallFont::SIZE + BigFont::SIZE;
+
+    /// The address of the small digit
+    pub(crate) fn small_digit_addr(&self, digit: u8) -> u16 {
+        self.addr + 5 * digit as u16
+    }
+
+    /// The address of the big digit
+    pub(crate) fn big_digit_addr(&self, digit: u8) -> u16 {
+        self.addr + SmallFont::SIZE as u16 + 10 * digit as u16
+    }
+
+    /// The memory the fonts occupy
+    pub(crate) fn range(&self) -> core::ops::Range<usize> {
+        self.addr as usize..self.addr as usize + Self::SIZE
+    }
+}
+
+impl Default for Fonts {
+    fn default() -> Self {
+        Fonts {
+            small: SmallFont::OCTO,
+            big: BigFont::OCTO,
+            addr: 0x50,
+        }
+    }
+}
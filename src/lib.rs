@@ -1,20 +1,84 @@
-use std::panic;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::ops::Range;
+
+#[cfg(feature = "alloc")]
+pub mod assembler;
+#[cfg(feature = "alloc")]
+mod cached;
+mod cdp1802;
+#[cfg(feature = "alloc")]
+pub mod debugger;
+pub mod disasm;
+mod display;
+mod error;
 mod font;
+mod loader;
+mod quirks;
+mod random;
+#[cfg(feature = "alloc")]
+mod recompiler;
+#[cfg(feature = "alloc")]
+mod save_state;
+mod scheduler;
+mod stack;
+mod sys_call;
+mod timing;
+mod trace;
+mod vip;
+
+#[cfg(feature = "alloc")]
+pub use cached::Engine;
+pub use display::{Display, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, PLANE_COUNT};
+pub use error::Chip8Error;
+pub use font::{BigFont, Fonts, SmallFont};
+pub use loader::ProgramImage;
+#[cfg(feature = "alloc")]
+pub use quirks::ParseQuirksError;
+pub use quirks::Quirks;
+pub use rand::RngCore;
+#[cfg(feature = "alloc")]
+pub use save_state::StateError;
+pub use scheduler::{RunSummary, DEFAULT_INSTRUCTIONS_PER_SECOND, TIMER_PERIOD};
+pub use stack::{StackDepth, FIXED_STACK_DEPTH};
+pub use sys_call::{SysCallHandler, SysCallPolicy};
+pub use timing::{Timing, VIP_CYCLES_PER_FRAME, VIP_CYCLES_PER_SECOND};
+pub use trace::{TraceEntry, TraceFormat};
+pub use vip::{Vip, VipImage, VIP_RAM_SIZE, VIP_ROM_SIZE};
+
+#[cfg(feature = "alloc")]
+use cached::DecodeCache;
+#[cfg(feature = "alloc")]
+use disasm::Instruction;
+use random::RandomSource;
+#[cfg(feature = "alloc")]
+use recompiler::Blocks;
+use scheduler::Clock;
+use stack::Stack;
+
+/// XO-CHIP extends the address space from 4 KiB to 64 KiB
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Programs are loaded, and start executing, at 0x200 (512) unless loaded with [`Chip8::load_image`]
+pub const PROGRAM_START: u16 = 0x200;
 
 pub struct Chip8 {
     /// Program should be loaded into memory starting at 0x200 (512)
-    memory: [u8; 4096],
-    /// 64 pixels wide, 32 pixels tall
-    /// indexed as `[row][col]` or `[y][x]`
-    pub display: [[bool; 64]; 32],
+    memory: [u8; MEMORY_SIZE],
+    /// 64x32 pixels, or 128x64 pixels in SUPER-CHIP high resolution mode
+    pub display: Display,
     /// points to the current instruction in memory
-    /// Only 12 bits are usable
     pc: u16,
     /// also called 'I'
     /// used to point at locations in memory
     index_reg: u16,
     /// Used to call and return from subroutines
-    stack: Vec<u16>,
+    stack: Stack,
     /// timer loop decrement is separate from emulation speed
     /// decremented at 60 Hz until it reaches 0
     delay_timer: u8,
@@ -25,6 +89,71 @@ pub struct Chip8 {
     /// also called V0 to VF
     /// VF is also used as a flag register
     registers: [u8; 16],
+    /// SUPER-CHIP "RPL user flags", persistent storage for registers written by FX75 and read by FX85
+    flags: [u8; 16],
+    /// set by the SUPER-CHIP 00FD instruction, the program doesn't execute any further instructions
+    exited: bool,
+    /// XO-CHIP bitmask of the planes that drawing, clearing and scrolling apply to
+    selected_planes: u8,
+    /// XO-CHIP 1-bit audio samples loaded by F002
+    audio_buffer: Option<[u8; 16]>,
+    /// XO-CHIP playback rate of the audio buffer, set by FX3A
+    pitch: u8,
+    /// used by CXNN
+    rng: RandomSource,
+    /// which interpretation to use for the ambiguous instructions
+    quirks: Quirks,
+    /// the digit sprites for FX29 and FX30, also copied into memory
+    fonts: Fonts,
+    /// instructions executed without an error, see [`Chip8::instruction_count`]
+    instruction_count: u64,
+    /// machine cycles elapsed with VIP timing, see [`Chip8::cycles`]
+    cycles: u64,
+    /// the time [`Chip8::run_for`] hasn't used up yet
+    clock: Clock,
+    /// what 0NNN does, see [`Chip8::with_sys_call_policy`]
+    sys_calls: SysCallPolicy,
+    /// called before every instruction, see [`Chip8::with_trace_hook`]
+    #[cfg(feature = "alloc")]
+    trace_hook: Option<TraceHook>,
+    /// how [`Chip8::step`] executes instructions
+    #[cfg(feature = "alloc")]
+    engine: Engine,
+    /// the instructions decoded by [`Engine::CachedDecode`]
+    #[cfg(feature = "alloc")]
+    decode_cache: DecodeCache,
+    /// the blocks translated by [`Engine::Recompiler`]
+    #[cfg(feature = "alloc")]
+    blocks: Blocks,
+}
+
+#[cfg(feature = "alloc")]
+type TraceHook = Box<dyn FnMut(&TraceEntry)>;
+
+/// The XO-CHIP audio pattern, a 128-sample 1-bit waveform that loops while the sound timer is non-zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    /// Samples are played from the most significant bit of the first byte to the least significant bit of the last byte
+    pub buffer: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// The default pitch, which plays samples at 4000 Hz
+    pub const DEFAULT_PITCH: u8 = 64;
+
+    /// Number of samples per second to play the buffer at
+    #[cfg(feature = "std")]
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// The values of the delay and sound timers, which count down at 60 Hz
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timers {
+    pub delay: u8,
+    pub sound: u8,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,42 +162,233 @@ pub enum DisplayState {
     NotUpdated,
 }
 
+/// What a frontend needs to run an emulator in real time and show and play its output,
+/// so that it can run either [`Chip8`] or a [`Vip`] with the original interpreter
+pub trait Machine {
+    /// See [`Chip8::run_for`]
+    fn run_for(
+        &mut self,
+        duration: core::time::Duration,
+        pressed_keys: [bool; 16],
+    ) -> Result<RunSummary, Chip8Error>;
+    fn display(&self) -> &Display;
+    fn is_sound_on(&self) -> bool;
+    /// The XO-CHIP audio pattern to play while the sound is on, or None for a plain beep
+    fn audio_pattern(&self) -> Option<AudioPattern>;
+    /// True once the program has stopped for good
+    fn has_exited(&self) -> bool;
+    fn instruction_count(&self) -> u64;
+}
+
 impl Chip8 {
     /// Loads a program and returns an emulator instance.
     /// A program consists of 16-bit instructions, but is provided as a byte array.
-    pub fn load_program(program: &[u8]) -> Self {
-        let mut memory = [0u8; 4096];
-        if program.len() > memory.len() - 512 {
-            panic!("Program is too large to load into memory");
-        }
-        // program should be loaded at address 0x200 (512)
-        let program_start_addr = 0x200_usize;
-        memory[program_start_addr..(program_start_addr + program.len())].copy_from_slice(program);
-        // Store fonts at addresses 0x50 to 0x9F
-        for (idx, font::Font(bytes)) in font::FONTS.iter().enumerate() {
-            let font_start_addr = 0x50 + 5 * idx;
-            memory[font_start_addr..(font_start_addr + 5)].copy_from_slice(bytes);
-        }
+    /// The random number generator is seeded randomly, see [`Chip8::with_seed`] for reproducible runs.
+    /// Without the `std` feature it always starts from the same seed.
+    pub fn load_program(program: &[u8], quirks: Quirks) -> Result<Self, Chip8Error> {
+        let image = ProgramImage {
+            segments: &[(PROGRAM_START, program)],
+            ..Default::default()
+        };
+        Self::load_image(&image, quirks)
+    }
+
+    /// Copies the fonts into memory that already holds the program
+    fn with_memory(mut memory: [u8; MEMORY_SIZE], pc: u16, fonts: Fonts, quirks: Quirks) -> Self {
+        let (small, big) = memory[fonts.range()].split_at_mut(SmallFont::SIZE);
+        small.copy_from_slice(fonts.small.as_bytes());
+        big.copy_from_slice(fonts.big.as_bytes());
 
         Chip8 {
             memory,
-            display: [[false; 64]; 32],
-            pc: program_start_addr as u16,
+            display: Display::default(),
+            pc,
             index_reg: 0x00,
-            stack: Vec::new(),
+            stack: Stack::default(),
             delay_timer: 0,
             sound_timer: 0,
             registers: [0u8; 16],
+            flags: [0u8; 16],
+            exited: false,
+            selected_planes: 0b01,
+            audio_buffer: None,
+            pitch: AudioPattern::DEFAULT_PITCH,
+            rng: RandomSource::from_seed(initial_seed()),
+            quirks,
+            fonts,
+            instruction_count: 0,
+            cycles: 0,
+            clock: Clock::default(),
+            sys_calls: SysCallPolicy::default(),
+            #[cfg(feature = "alloc")]
+            trace_hook: None,
+            #[cfg(feature = "alloc")]
+            engine: Engine::default(),
+            #[cfg(feature = "alloc")]
+            decode_cache: DecodeCache::default(),
+            #[cfg(feature = "alloc")]
+            blocks: Blocks::default(),
+        }
+    }
+
+    /// Seeds the random number generator used by CXNN, so that runs of the program are reproducible.
+    /// The generator's state is included in save states.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = RandomSource::from_seed(seed);
+        self
+    }
+
+    /// Uses a custom random number generator for CXNN, e.g. a scripted sequence for tests.
+    /// Save states don't capture its state, and restoring one keeps using this generator.
+    #[cfg(feature = "alloc")]
+    pub fn with_rng(mut self, rng: impl RngCore + 'static) -> Self {
+        self.rng = RandomSource::Custom(Box::new(rng));
+        self
+    }
+
+    /// Calls `hook` with the machine state before every instruction, e.g. to log a trace.
+    /// The entry's `Display` implementation formats it as one line of a trace log.
+    #[cfg(feature = "alloc")]
+    pub fn with_trace_hook(mut self, hook: impl FnMut(&TraceEntry) + 'static) -> Self {
+        self.trace_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Executes instructions with `engine` instead of the interpreter
+    #[cfg(feature = "alloc")]
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self.clear_code();
+        self
+    }
+
+    /// How [`Chip8::step`] executes instructions
+    #[cfg(feature = "alloc")]
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// The interpretation of the ambiguous instructions this emulator was loaded with.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// The fonts the program was loaded with, and their address
+    pub fn fonts(&self) -> &Fonts {
+        &self.fonts
+    }
+
+    /// The address of the next instruction to execute
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The index register, I
+    pub fn index(&self) -> u16 {
+        self.index_reg
+    }
+
+    /// V0 to VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// The SUPER-CHIP RPL user flags saved by FX75
+    pub fn flags(&self) -> &[u8; 16] {
+        &self.flags
+    }
+
+    /// Return addresses of the subroutines being executed, the most recent call last
+    pub fn stack(&self) -> &[u16] {
+        self.stack.as_slice()
+    }
+
+    pub fn timers(&self) -> Timers {
+        Timers {
+            delay: self.delay_timer,
+            sound: self.sound_timer,
         }
     }
 
+    /// The whole address space, including the fonts below 0x200
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    /// Writes a byte of memory
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        #[cfg(feature = "alloc")]
+        self.invalidate_code(addr as usize..addr as usize + 1);
+    }
+
+    /// Sets register VX.
+    /// Panics if `x` is greater than 0xF.
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.registers[x as usize] = value;
+    }
+
+    /// Sets the address of the next instruction to execute
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Sets the index register, I
+    pub fn set_index(&mut self, index: u16) {
+        self.index_reg = index;
+    }
+
     /// Given the set of keys that are currently pressed, execute the next program instruction and update the emulator state.
-    pub fn step(&mut self, pressed_keys: [bool; 16]) -> DisplayState {
+    /// Does nothing once the program has exited.
+    /// On error, the emulator is left in the state it was in partway through the failed instruction.
+    /// A draw returns right away even with [`Quirks::display_wait`], see [`Chip8::run_for`] for that.
+    pub fn step(&mut self, pressed_keys: [bool; 16]) -> Result<DisplayState, Chip8Error> {
+        if self.exited {
+            return Ok(DisplayState::NotUpdated);
+        }
+        self.run_instruction(|chip8| chip8.dispatch(pressed_keys))
+    }
+
+    /// Executes the instruction at pc with `execute`, with the tracing and counting every instruction gets
+    pub(crate) fn run_instruction(
+        &mut self,
+        execute: impl FnOnce(&mut Chip8) -> Result<DisplayState, Chip8Error>,
+    ) -> Result<DisplayState, Chip8Error> {
+        #[cfg(feature = "alloc")]
+        if let Some(mut hook) = self.trace_hook.take() {
+            hook(&self.trace_entry());
+            self.trace_hook = Some(hook);
+        }
+        let cost = match self.clock.timing {
+            Timing::Instructions => 0,
+            Timing::Vip => self.vip_cycles(),
+        };
+        let result = execute(self);
+        if result.is_ok() {
+            self.instruction_count += 1;
+            self.cycles += cost as u64;
+        }
+        result
+    }
+
+    /// Executes the instruction at pc with the selected engine
+    fn dispatch(&mut self, pressed_keys: [bool; 16]) -> Result<DisplayState, Chip8Error> {
+        #[cfg(feature = "alloc")]
+        match self.engine {
+            Engine::Interpreter => {}
+            Engine::CachedDecode => return self.step_cached(pressed_keys),
+            Engine::Recompiler => return self.step_recompiled(pressed_keys),
+        }
+        self.interpret(pressed_keys)
+    }
+
+    /// Fetches, decodes and executes the instruction at pc, the reference for the other engines
+    fn interpret(&mut self, pressed_keys: [bool; 16]) -> Result<DisplayState, Chip8Error> {
         // fetch
-        let first_byte = self.memory[self.pc as usize];
-        let second_byte = self.memory[self.pc as usize + 1];
-        let inst = ((first_byte as u16) << 8) | (second_byte as u16);
-        self.pc += 2;
+        let pc = self.pc;
+        let inst = self.read_u16(pc);
+        let [first_byte, second_byte] = inst.to_be_bytes();
+        self.pc = self.pc.wrapping_add(2);
 
         // decode
         let first_half_byte = first_byte >> 4;
@@ -83,19 +403,67 @@ impl Chip8 {
         // a 12-bit immediate memory address, comprised of half-bytes 2-4
         let nnn = inst & 0x0fff;
 
+        let invalid_instruction = Chip8Error::InvalidInstruction { pc, opcode: inst };
+        let out_of_bounds = |addr| Chip8Error::MemoryOutOfBounds {
+            pc,
+            opcode: inst,
+            addr,
+        };
+
         // execute
         match first_half_byte {
             0x0 => {
                 match nnn {
                     0x0E0 => {
                         // clear screen
-                        self.display = [[false; 64]; 32];
-                        return DisplayState::Updated;
+                        self.display.clear(self.selected_planes);
+                        return Ok(DisplayState::Updated);
                     }
                     0x0EE => {
-                        self.pc = self.stack.pop().expect("Can't return from function call without a return address on the stack.");
+                        self.pc = self
+                            .stack
+                            .pop()
+                            .ok_or(Chip8Error::StackUnderflow { pc, opcode: inst })?;
+                    }
+                    // 00CN
+                    // scroll the display down by N pixels
+                    0x0C0..=0x0CF => {
+                        self.display.scroll_down(self.selected_planes, n as usize);
+                        return Ok(DisplayState::Updated);
+                    }
+                    // 00DN
+                    // scroll the display up by N pixels
+                    0x0D0..=0x0DF => {
+                        self.display.scroll_up(self.selected_planes, n as usize);
+                        return Ok(DisplayState::Updated);
                     }
-                    _ => panic!("Invalid instruction: {inst:#x}"),
+                    // scroll the display right by 4 pixels
+                    0x0FB => {
+                        self.display.scroll_right(self.selected_planes, 4);
+                        return Ok(DisplayState::Updated);
+                    }
+                    // scroll the display left by 4 pixels
+                    0x0FC => {
+                        self.display.scroll_left(self.selected_planes, 4);
+                        return Ok(DisplayState::Updated);
+                    }
+                    // exit the interpreter
+                    0x0FD => {
+                        self.exited = true;
+                    }
+                    // switch to 64x32 low resolution mode
+                    0x0FE => {
+                        self.display.set_hires(false);
+                        return Ok(DisplayState::Updated);
+                    }
+                    // switch to 128x64 high resolution mode
+                    0x0FF => {
+                        self.display.set_hires(true);
+                        return Ok(DisplayState::Updated);
+                    }
+                    0x000 => return Err(invalid_instruction),
+                    // call a machine code routine at NNN
+                    _ => return self.sys_call(pc, inst),
                 }
             }
             0x1 => {
@@ -106,25 +474,51 @@ impl Chip8 {
             0x2 => {
                 // call function at address NNN
                 // push the return address onto the stack first
-                self.stack.push(self.pc);
+                if !self.stack.push(self.pc) {
+                    return Err(Chip8Error::StackOverflow { pc, opcode: inst });
+                }
                 self.pc = nnn;
             }
             0x3 => {
                 // conditional skip
                 if self.registers[x] == nn {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
             0x4 => {
                 // conditional skip
                 if self.registers[x] != nn {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
             0x5 => {
-                // conditional skip
-                if self.registers[x] == self.registers[y] {
-                    self.pc += 2;
+                match n {
+                    // conditional skip
+                    0x0 => {
+                        if self.registers[x] == self.registers[y] {
+                            self.skip_instruction();
+                        }
+                    }
+                    // 5XY2
+                    // store registers VX through VY in memory starting at I, in either order
+                    0x2 => {
+                        let range = self.index_range(x.abs_diff(y) + 1).map_err(out_of_bounds)?;
+                        for (addr, reg) in range.clone().zip(register_range(x, y)) {
+                            self.memory[addr] = self.registers[reg];
+                        }
+                        // the other engines fall back to this one for some instructions
+                        #[cfg(feature = "alloc")]
+                        self.invalidate_code(range);
+                    }
+                    // 5XY3
+                    // load registers VX through VY from memory starting at I, in either order
+                    0x3 => {
+                        let range = self.index_range(x.abs_diff(y) + 1).map_err(out_of_bounds)?;
+                        for (addr, reg) in range.zip(register_range(x, y)) {
+                            self.registers[reg] = self.memory[addr];
+                        }
+                    }
+                    _ => return Err(invalid_instruction),
                 }
             }
             0x6 => {
@@ -144,11 +538,26 @@ impl Chip8 {
                     // set
                     0x0 => self.registers[x] = self.registers[y],
                     // or
-                    0x1 => self.registers[x] |= self.registers[y],
+                    0x1 => {
+                        self.registers[x] |= self.registers[y];
+                        if self.quirks.logic_resets_vf {
+                            self.registers[0xF] = 0;
+                        }
+                    }
                     // and
-                    0x2 => self.registers[x] &= self.registers[y],
+                    0x2 => {
+                        self.registers[x] &= self.registers[y];
+                        if self.quirks.logic_resets_vf {
+                            self.registers[0xF] = 0;
+                        }
+                    }
                     // xor
-                    0x3 => self.registers[x] ^= self.registers[y],
+                    0x3 => {
+                        self.registers[x] ^= self.registers[y];
+                        if self.quirks.logic_resets_vf {
+                            self.registers[0xF] = 0;
+                        }
+                    }
                     // add
                     0x4 => {
                         {
@@ -163,27 +572,44 @@ impl Chip8 {
                         }
                     }
                     // subtract
-                    0x5 => self.registers[x] = self.registers[x].wrapping_sub(self.registers[y]),
-                    0x7 => self.registers[x] = self.registers[y].wrapping_sub(self.registers[x]),
+                    // the flag is set when there is no borrow, and written last so it wins when X is F
+                    0x5 => {
+                        let (vx, vy) = (self.registers[x], self.registers[y]);
+                        self.registers[x] = vx.wrapping_sub(vy);
+                        self.registers[0xF] = (vx >= vy) as u8;
+                    }
+                    0x7 => {
+                        let (vx, vy) = (self.registers[x], self.registers[y]);
+                        self.registers[x] = vy.wrapping_sub(vx);
+                        self.registers[0xF] = (vy >= vx) as u8;
+                    }
                     // shift
-                    // (Optional, or configurable) Set VX to the value of VY
+                    // the original interpreter sets VX to the value of VY before shifting
                     0x6 => {
-                        // set flag register to low bit
-                        self.registers[0xF] = self.registers[x] & 0x1;
+                        if !self.quirks.shift_in_place {
+                            self.registers[x] = self.registers[y];
+                        }
+                        // set flag register to the bit shifted out
+                        let shifted_out = self.registers[x] & 0x1;
                         self.registers[x] >>= 1;
+                        self.registers[0xF] = shifted_out;
                     }
                     0xE => {
-                        // set flag register to high bit
-                        self.registers[0xF] = self.registers[x] & 0x80;
+                        if !self.quirks.shift_in_place {
+                            self.registers[x] = self.registers[y];
+                        }
+                        // set flag register to the bit shifted out
+                        let shifted_out = self.registers[x] >> 7;
                         self.registers[x] <<= 1;
+                        self.registers[0xF] = shifted_out;
                     }
-                    _ => panic!("Invalid instruction: {inst:#x}"),
+                    _ => return Err(invalid_instruction),
                 }
             }
             0x9 => {
                 // conditional skip
                 if self.registers[x] != self.registers[y] {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
             0xa => {
@@ -193,43 +619,28 @@ impl Chip8 {
             }
             0xb => {
                 // jump with offset
-                self.pc = nnn + self.registers[0] as u16;
+                // CHIP-48 and SUPER-CHIP read this as BXNN and add VX instead of V0
+                let offset_reg = if self.quirks.jump_uses_vx { x } else { 0 };
+                self.pc = nnn + self.registers[offset_reg] as u16;
             }
             0xc => {
                 // random
-                self.registers[x] = rand::random::<u8>() & nn;
+                self.registers[x] = self.rng.next_u8() & nn;
             }
             0xd => {
                 // DXYN
-                // draw
-                let mut y_coord = self.registers[y] as usize % 32;
-                self.registers[0xF] = 0;
-                let bytes =
-                    &self.memory[self.index_reg as usize..(self.index_reg as usize + n as usize)];
-                for byte in bytes {
-                    let mut x_coord = self.registers[x] as usize % 64;
-                    for bit_idx in 0..8 {
-                        let mask = 0b1000_0000u8 >> bit_idx;
-                        let bit = byte & mask > 0;
-                        if bit {
-                            if self.display[y_coord][x_coord] {
-                                self.display[y_coord][x_coord] = false;
-                                self.registers[0xF] = 1;
-                            } else {
-                                self.display[y_coord][x_coord] = true;
-                            }
-                        }
-                        x_coord += 1;
-                        if x_coord == 64 {
-                            break;
-                        }
-                    }
-                    y_coord += 1;
-                    if y_coord == 32 {
-                        break;
-                    }
-                }
-                return DisplayState::Updated;
+                // draw an 8xN sprite, or a 16x16 sprite when N is 0
+                let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let collision = self
+                    .draw_sprite(
+                        self.registers[x] as usize,
+                        self.registers[y] as usize,
+                        sprite_width,
+                        sprite_height,
+                    )
+                    .map_err(out_of_bounds)?;
+                self.registers[0xF] = collision as u8;
+                return Ok(DisplayState::Updated);
             }
             0xe => {
                 // skip if key
@@ -238,20 +649,40 @@ impl Chip8 {
                     // Skip next instruction if key with the value of Vx is pressed.
                     0x9e => {
                         if vx < 16 && pressed_keys[vx as usize] {
-                            self.pc += 2;
+                            self.skip_instruction();
                         }
                     }
                     // skips one instruction if the key corresponding to the value in VX is not pressed.
                     0xa1 => {
                         if vx >= 16 || !pressed_keys[vx as usize] {
-                            self.pc += 2;
+                            self.skip_instruction();
                         }
                     }
-                    _ => panic!("Invalid instruction: {inst:#x}"),
+                    _ => return Err(invalid_instruction),
                 }
             }
             0xf => {
                 match nn {
+                    // F000 NNNN
+                    // load I with the 16-bit address stored in the next two bytes
+                    0x00 if x == 0 => {
+                        self.index_reg = self.read_u16(self.pc);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    // FN01
+                    // select the bitplanes to draw to, clear and scroll
+                    0x01 => self.selected_planes = x as u8 & ((1 << PLANE_COUNT) - 1),
+                    // F002
+                    // load the 16-byte audio pattern buffer from memory starting at I
+                    0x02 if x == 0 => {
+                        let mut buffer = [0u8; 16];
+                        let range = self.index_range(16).map_err(out_of_bounds)?;
+                        buffer.copy_from_slice(&self.memory[range]);
+                        self.audio_buffer = Some(buffer);
+                    }
+                    // FX3A
+                    // set the playback rate of the audio pattern buffer
+                    0x3a => self.pitch = self.registers[x],
                     // timers
                     // FX07 sets VX to the current value of the delay timer
                     // FX15 sets the delay timer to the value in VX
@@ -273,17 +704,32 @@ impl Chip8 {
                         // This has the effect of running the same instruction repeatedly.
                         match pressed_keys.iter().position(|&pressed| pressed) {
                             Some(idx) => self.registers[x] = idx as u8,
-                            None => self.pc -= 2,
+                            None => self.pc = pc,
                         }
                     }
                     0x29 => {
                         // The index register I is set to the address of the hexadecimal character in VX.
                         let vx = self.registers[x];
                         if vx > 15 {
-                            panic!("Invalid value for VX while executing 0xfx29: {vx:?}")
+                            return Err(Chip8Error::InvalidFontCharacter {
+                                pc,
+                                opcode: inst,
+                                value: vx,
+                            });
                         }
-                        let font_addr = 0x50 + 5 * vx as u16;
-                        self.index_reg = font_addr;
+                        self.index_reg = self.fonts.small_digit_addr(vx);
+                    }
+                    0x30 => {
+                        // The index register I is set to the address of the big hexadecimal character in VX.
+                        let vx = self.registers[x];
+                        if vx > 15 {
+                            return Err(Chip8Error::InvalidFontCharacter {
+                                pc,
+                                opcode: inst,
+                                value: vx,
+                            });
+                        }
+                        self.index_reg = self.fonts.big_digit_addr(vx);
                     }
                     // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
                     0x33 => {
@@ -291,31 +737,147 @@ impl Chip8 {
                         let ones = decimal_val % 10;
                         let tens = (decimal_val / 10) % 10;
                         let hundreds = decimal_val / 100;
-                        self.memory[self.index_reg as usize] = hundreds;
-                        self.memory[self.index_reg as usize + 1] = tens;
-                        self.memory[self.index_reg as usize + 2] = ones;
+                        let range = self.index_range(3).map_err(out_of_bounds)?;
+                        self.memory[range.clone()].copy_from_slice(&[hundreds, tens, ones]);
+                        #[cfg(feature = "alloc")]
+                        self.invalidate_code(range);
                     }
                     // Store registers V0 through Vx in memory starting at location I.
                     0x55 => {
-                        for i in 0..=x {
-                            self.memory[self.index_reg as usize + i] = self.registers[i]
+                        let range = self.index_range(x + 1).map_err(out_of_bounds)?;
+                        self.memory[range.clone()].copy_from_slice(&self.registers[..=x]);
+                        #[cfg(feature = "alloc")]
+                        self.invalidate_code(range);
+                        if self.quirks.load_store_increments_index {
+                            self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
                         }
                     }
                     // Read registers V0 through Vx from memory starting at location I.
                     0x65 => {
-                        for i in 0..=x {
-                            self.registers[i] = self.memory[self.index_reg as usize + i];
+                        let range = self.index_range(x + 1).map_err(out_of_bounds)?;
+                        self.registers[..=x].copy_from_slice(&self.memory[range]);
+                        if self.quirks.load_store_increments_index {
+                            self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
                         }
                     }
-                    _ => panic!("Invalid instruction: {inst:#x}"),
+                    // Store registers V0 through Vx in the RPL user flags.
+                    0x75 => self.flags[..=x].copy_from_slice(&self.registers[..=x]),
+                    // Read registers V0 through Vx from the RPL user flags.
+                    0x85 => self.registers[..=x].copy_from_slice(&self.flags[..=x]),
+                    _ => return Err(invalid_instruction),
+                }
+            }
+            _ => unreachable!("programming error: unhandled leading half-byte: {inst:#x}"),
+        }
+        Ok(DisplayState::NotUpdated)
+    }
+
+    /// XORs a sprite read from memory at I onto the display at (x, y).
+    /// Each row of the sprite is `width / 8` bytes.
+    /// When more than one plane is selected, the sprite data for each plane follows the previous plane's.
+    /// Returns true if any pixel was turned off,
+    /// or the first address past the end of memory if the sprite data doesn't fit.
+    fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<bool, usize> {
+        let (display_width, display_height) = (self.display.width(), self.display.height());
+        // the starting position wraps, the rest of the sprite is clipped or wrapped depending on the quirk
+        let start_x = x % display_width;
+        let start_y = y % display_height;
+        let bytes_per_row = width / 8;
+        let sprite_len = bytes_per_row * height;
+        let plane_count = self.selected_planes.count_ones() as usize;
+        let mut sprite_addr = self.index_range(sprite_len * plane_count)?.start;
+        let mut collision = false;
+        for plane in (0..PLANE_COUNT).map(|idx| 1u8 << idx) {
+            if self.selected_planes & plane == 0 {
+                continue;
+            }
+            let bytes = &self.memory[sprite_addr..(sprite_addr + sprite_len)];
+            sprite_addr += sprite_len;
+            for (row_offset, row) in bytes.chunks_exact(bytes_per_row).enumerate() {
+                let mut y_coord = start_y + row_offset;
+                if y_coord >= display_height {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    y_coord %= display_height;
+                }
+                for bit_idx in 0..width {
+                    let mut x_coord = start_x + bit_idx;
+                    if x_coord >= display_width {
+                        if self.quirks.clip_sprites {
+                            break;
+                        }
+                        x_coord %= display_width;
+                    }
+                    let mask = 0b1000_0000u8 >> (bit_idx % 8);
+                    let bit = row[bit_idx / 8] & mask > 0;
+                    if bit {
+                        collision |= self.display.toggle(x_coord, y_coord, plane);
+                    }
                 }
             }
-            _ => panic!("programming error: unhandled leading half-byte: {inst:#x}"),
         }
-        DisplayState::NotUpdated
+        Ok(collision)
     }
 
-    /// Should be called at 60 Hz
+    /// The machine state before the instruction at pc executes
+    #[cfg(feature = "alloc")]
+    fn trace_entry(&self) -> TraceEntry {
+        let opcode = self.read_u16(self.pc);
+        TraceEntry {
+            pc: self.pc,
+            opcode,
+            instruction: Instruction::decode(opcode, self.read_u16(self.pc.wrapping_add(2))),
+            registers: self.registers,
+            index: self.index_reg,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack_depth: self.stack().len(),
+        }
+    }
+
+    /// The `len` bytes of memory starting at I,
+    /// or the first address past the end of memory if they don't fit
+    fn index_range(&self, len: usize) -> Result<Range<usize>, usize> {
+        let start = self.index_reg as usize;
+        if start + len > MEMORY_SIZE {
+            Err(MEMORY_SIZE)
+        } else {
+            Ok(start..start + len)
+        }
+    }
+
+    /// Reads a big-endian 16-bit value, wrapping around at the end of memory
+    fn read_u16(&self, addr: u16) -> u16 {
+        let first_byte = self.memory[addr as usize];
+        let second_byte = self.memory[addr.wrapping_add(1) as usize];
+        ((first_byte as u16) << 8) | (second_byte as u16)
+    }
+
+    /// Skips the next instruction, which is 4 bytes long if it is the XO-CHIP F000 NNNN long load
+    fn skip_instruction(&mut self) {
+        let next_inst = self.read_u16(self.pc);
+        let inst_len = if next_inst == 0xF000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(inst_len);
+    }
+
+    /// The number of instructions executed without an error since the program was loaded
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// True once the program has executed the SUPER-CHIP exit instruction, 00FD.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Should be called at 60 Hz when executing instructions with [`Chip8::step`], [`Chip8::run_for`] calls it itself
     pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -328,4 +890,131 @@ impl Chip8 {
     pub fn is_sound_on(&self) -> bool {
         self.sound_timer > 0
     }
+
+    /// The XO-CHIP audio pattern to play while the sound is on,
+    /// or None if the program hasn't loaded one and expects a plain beep.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_buffer.map(|buffer| AudioPattern {
+            buffer,
+            pitch: self.pitch,
+        })
+    }
+}
+
+impl Machine for Chip8 {
+    fn run_for(
+        &mut self,
+        duration: core::time::Duration,
+        pressed_keys: [bool; 16],
+    ) -> Result<RunSummary, Chip8Error> {
+        Chip8::run_for(self, duration, pressed_keys)
+    }
+
+    fn display(&self) -> &Display {
+        &self.display
+    }
+
+    fn is_sound_on(&self) -> bool {
+        Chip8::is_sound_on(self)
+    }
+
+    fn audio_pattern(&self) -> Option<AudioPattern> {
+        Chip8::audio_pattern(self)
+    }
+
+    fn has_exited(&self) -> bool {
+        Chip8::has_exited(self)
+    }
+
+    fn instruction_count(&self) -> u64 {
+        Chip8::instruction_count(self)
+    }
+}
+
+#[cfg(feature = "std")]
+fn initial_seed() -> u64 {
+    rand::random()
+}
+
+/// Without the standard library there's no source of entropy to seed from
+#[cfg(not(feature = "std"))]
+fn initial_seed() -> u64 {
+    0
+}
+
+/// Registers from VX to VY, counting down if Y is less than X
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    (0..=x.abs_diff(y)).map(move |offset| if x <= y { x + offset } else { x - offset })
+}
+
+#[test]
+fn test_step_errors() {
+    let mut emulator = Chip8::load_program(&[0x00, 0xEE], Quirks::default()).unwrap();
+    assert_eq!(
+        emulator.step([false; 16]),
+        Err(Chip8Error::StackUnderflow {
+            pc: 0x200,
+            opcode: 0x00EE
+        })
+    );
+    // v0 := 0x10, i := font character in v0
+    let mut emulator = Chip8::load_program(&[0x60, 0x10, 0xF0, 0x29], Quirks::default()).unwrap();
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(
+        emulator.step([false; 16]),
+        Err(Chip8Error::InvalidFontCharacter {
+            pc: 0x202,
+            opcode: 0xF029,
+            value: 0x10
+        })
+    );
+    // i := long 0xFFFF, save v0 - v1
+    let program = [0xF0, 0x00, 0xFF, 0xFF, 0xF1, 0x55];
+    let mut emulator = Chip8::load_program(&program, Quirks::default()).unwrap();
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(
+        emulator.step([false; 16]),
+        Err(Chip8Error::MemoryOutOfBounds {
+            pc: 0x204,
+            opcode: 0xF155,
+            addr: MEMORY_SIZE
+        })
+    );
+    assert_eq!(
+        Chip8::load_program(&[0x12, 0x00], Quirks::default())
+            .unwrap()
+            .step([false; 16]),
+        Ok(DisplayState::NotUpdated)
+    );
+    assert!(matches!(
+        Chip8::load_program(&[0u8; MEMORY_SIZE], Quirks::default()),
+        Err(Chip8Error::ProgramTooLarge { .. })
+    ));
+}
+
+#[test]
+fn test_inspection() {
+    // v0 := 0x42, i := 0x300, call 0x208
+    let program = [0x60, 0x42, 0xA3, 0x00, 0x22, 0x08];
+    let mut emulator = Chip8::load_program(&program, Quirks::default()).unwrap();
+    for _ in 0..3 {
+        emulator.step([false; 16]).unwrap();
+    }
+    assert_eq!(emulator.pc(), 0x208);
+    assert_eq!(emulator.index(), 0x300);
+    assert_eq!(emulator.registers()[0], 0x42);
+    assert_eq!(emulator.stack(), [0x206]);
+    assert_eq!(emulator.timers(), Timers { delay: 0, sound: 0 });
+    assert_eq!(emulator.memory()[0x200..0x206], program);
+
+    // v1 := v0 at 0x208
+    emulator.poke(0x208, 0x81);
+    emulator.poke(0x209, 0x00);
+    emulator.set_register(0, 7);
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(emulator.registers()[1], 7);
+    emulator.set_pc(0x200);
+    emulator.set_index(0x400);
+    emulator.step([false; 16]).unwrap();
+    assert_eq!((emulator.pc(), emulator.index()), (0x202, 0x400));
 }
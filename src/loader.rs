@@ -0,0 +1,185 @@
+use crate::{Chip8, Chip8Error, Fonts, Quirks, MEMORY_SIZE, PROGRAM_START};
+
+/// How to lay out a program in memory, for interpreters and tools that don't use a single image at 0x200.
+///
+/// ```
+/// # use chiprs::{Chip8, ProgramImage, Quirks};
+/// // an ETI-660 program
+/// let code = [0x16, 0x00];
+/// let image = ProgramImage {
+///     start: 0x600,
+///     entry: 0x600,
+///     segments: &[(0x600, &code)],
+///     ..Default::default()
+/// };
+/// let emulator = Chip8::load_image(&image, Quirks::vip()).unwrap();
+/// assert_eq!(emulator.pc(), 0x600);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramImage<'a> {
+    /// The lowest address the program may occupy. Memory below it belongs to the interpreter.
+    pub start: u16,
+    /// The address of the first instruction
+    pub entry: u16,
+    /// Blocks of bytes copied to their addresses, e.g. code and data that were built separately
+    pub segments: &'a [(u16, &'a [u8])],
+    /// The fonts, which can't overlap the segments
+    pub fonts: Fonts,
+}
+
+impl Chip8 {
+    /// Loads a program made of one or more segments and returns an emulator instance
+    /// that starts executing at the image's entry point.
+    ///
+    /// Fails if a segment lies below the start address, runs past the end of memory,
+    /// or overlaps another segment or the fonts, if the fonts don't fit in memory,
+    /// or if the entry point is below the start address.
+    pub fn load_image(image: &ProgramImage<'_>, quirks: Quirks) -> Result<Self, Chip8Error> {
+        let reserved_end = image.start;
+        let fonts = image.fonts.range();
+        if fonts.end > MEMORY_SIZE {
+            return Err(Chip8Error::FontsOverlap {
+                addr: image.fonts.addr,
+            });
+        }
+        let mut memory = [0u8; MEMORY_SIZE];
+        for (idx, &(addr, bytes)) in image.segments.iter().enumerate() {
+            if addr < reserved_end {
+                return Err(Chip8Error::ReservedMemory { addr, reserved_end });
+            }
+            let start = addr as usize;
+            if bytes.len() > MEMORY_SIZE - start {
+                return Err(Chip8Error::ProgramTooLarge {
+                    size: bytes.len(),
+                    max_size: MEMORY_SIZE - start,
+                });
+            }
+            let end = start + bytes.len();
+            let overlap = image.segments[..idx].iter().find(|(other, other_bytes)| {
+                let other = *other as usize;
+                start < other + other_bytes.len() && other < end
+            });
+            if let Some(&(other, _)) = overlap {
+                return Err(Chip8Error::OverlappingSegments {
+                    addr: addr.max(other),
+                });
+            }
+            if start < fonts.end && fonts.start < end {
+                return Err(Chip8Error::FontsOverlap {
+                    addr: start.max(fonts.start) as u16,
+                });
+            }
+            memory[start..end].copy_from_slice(bytes);
+        }
+        if image.entry < reserved_end {
+            return Err(Chip8Error::ReservedMemory {
+                addr: image.entry,
+                reserved_end,
+            });
+        }
+        Ok(Chip8::with_memory(memory, image.entry, image.fonts, quirks))
+    }
+}
+
+impl Default for ProgramImage<'_> {
+    /// No program at all, starting at 0x200
+    fn default() -> Self {
+        ProgramImage {
+            start: PROGRAM_START,
+            entry: PROGRAM_START,
+            segments: &[],
+            fonts: Fonts::default(),
+        }
+    }
+}
+
+#[test]
+fn test_load_image() {
+    let code = [0x12, 0x00];
+    let data = [0xAA, 0xBB];
+    let image = ProgramImage {
+        start: 0x600,
+        entry: 0x700,
+        segments: &[(0x700, &code), (0x600, &data)],
+        ..Default::default()
+    };
+    let emulator = Chip8::load_image(&image, Quirks::default()).unwrap();
+    assert_eq!(emulator.pc(), 0x700);
+    assert_eq!(emulator.memory()[0x700..0x702], code);
+    assert_eq!(emulator.memory()[0x600..0x602], data);
+    // the fonts are still loaded
+    assert_eq!(emulator.memory()[0x50], 0xF0);
+
+    fn load(image: ProgramImage<'_>) -> Result<(), Chip8Error> {
+        Chip8::load_image(&image, Quirks::default()).map(|_| ())
+    }
+    assert_eq!(
+        load(ProgramImage {
+            segments: &[(0x200, &code)],
+            ..image
+        }),
+        Err(Chip8Error::ReservedMemory {
+            addr: 0x200,
+            reserved_end: 0x600
+        })
+    );
+    assert_eq!(
+        load(ProgramImage {
+            start: 0x100,
+            entry: 0x100,
+            segments: &[(0x100, &code)],
+            ..Default::default()
+        }),
+        Err(Chip8Error::FontsOverlap { addr: 0x100 })
+    );
+    // fonts after the program
+    let fonts = Fonts {
+        addr: 0x1000,
+        ..Fonts::default()
+    };
+    let emulator = Chip8::load_image(
+        &ProgramImage {
+            segments: &[(0x200, &code)],
+            fonts,
+            ..Default::default()
+        },
+        Quirks::default(),
+    )
+    .unwrap();
+    assert_eq!(emulator.memory()[0x1000..0x1005], fonts.small.0[0]);
+    assert_eq!(emulator.memory()[0x50], 0);
+    assert_eq!(
+        load(ProgramImage {
+            fonts: Fonts {
+                addr: 0xFFF0,
+                ..Fonts::default()
+            },
+            ..Default::default()
+        }),
+        Err(Chip8Error::FontsOverlap { addr: 0xFFF0 })
+    );
+    assert_eq!(
+        load(ProgramImage {
+            segments: &[(0x600, &[0; 0x101]), (0x700, &code)],
+            ..image
+        }),
+        Err(Chip8Error::OverlappingSegments { addr: 0x700 })
+    );
+    assert_eq!(
+        load(ProgramImage {
+            entry: 0x300,
+            ..image
+        }),
+        Err(Chip8Error::ReservedMemory {
+            addr: 0x300,
+            reserved_end: 0x600
+        })
+    );
+    assert!(matches!(
+        load(ProgramImage {
+            segments: &[(0xFFFF, &code)],
+            ..Default::default()
+        }),
+        Err(Chip8Error::ProgramTooLarge { max_size: 1, .. })
+    ));
+}
@@ -1,34 +1,88 @@
+#[cfg(not(any(feature = "native", feature = "terminal")))]
+compile_error!("the chiprs binary needs the native or terminal feature, or both");
+
+#[cfg(feature = "native")]
 extern crate sdl2;
 
+mod capture;
+mod debug_console;
+#[cfg(feature = "native")]
 mod native_io;
+mod rewind;
+#[cfg(feature = "terminal")]
 mod terminal_io;
 
 use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
 use std::io::ErrorKind;
+use std::io::Write as _;
+use std::ops::Range;
+use std::path::Path;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::time::Duration;
 use std::time::Instant;
 
 use clap::Parser;
 
-use chiprs::{Chip8, DisplayState};
+use capture::FrameCapture;
+use chiprs::assembler::assemble;
+use chiprs::disasm::{Disassembly, Syntax};
+use chiprs::{
+    AudioPattern, BigFont, Chip8, Chip8Error, Display, DisplayState, Engine, Fonts, Machine,
+    ProgramImage, Quirks, RunSummary, SmallFont, StackDepth, SysCallPolicy, Timing, TraceEntry,
+    TraceFormat, Vip, VipImage, DEFAULT_INSTRUCTIONS_PER_SECOND, TIMER_PERIOD,
+};
+use debug_console::{ConsoleAction, DebugConsole};
+#[cfg(feature = "native")]
 use native_io::NativeWindow;
+use rewind::RewindBuffer;
+#[cfg(feature = "terminal")]
 use terminal_io::TerminalWindow;
 
-const FRAMES_PER_SECOND: u32 = 120;
-const INSTRUCTIONS_PER_FRAME: u32 = 10;
+/// The display refreshes at the same rate as the timers count down
+const FRAMES_PER_SECOND: u32 = 60;
+const FRAME_TIME: Duration = TIMER_PERIOD;
+/// The most time to emulate in one frame, so that the program doesn't race to catch up
+/// after the process was suspended or paused in the debugger
+const MAX_FRAME_TIME: Duration = Duration::from_millis(100);
 
 trait IODevice {
     /// Returns a bitset of the keys that are currently pressed.
     fn poll_input(&mut self) -> UserInput;
-    fn render(&mut self, display: &[[bool; 64]; 32]) -> Result<(), Box<dyn Error>>;
+    fn render(&mut self, display: &Display) -> Result<(), Box<dyn Error>>;
     fn pause_beep(&mut self);
-    fn resume_beep(&mut self);
+    /// Plays the XO-CHIP audio pattern if there is one, otherwise a plain tone
+    fn resume_beep(&mut self, pattern: Option<AudioPattern>);
+}
+
+/// Stands in for a window when running headless: no keys are ever pressed and nothing is shown
+struct NullDevice;
+
+impl IODevice for NullDevice {
+    fn poll_input(&mut self) -> UserInput {
+        UserInput::PressedKeys([false; 16])
+    }
+
+    fn render(&mut self, _display: &Display) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn pause_beep(&mut self) {}
+
+    fn resume_beep(&mut self, _pattern: Option<AudioPattern>) {}
 }
 
 enum UserInput {
     PressedKeys([bool; 16]),
+    /// Save the emulator state to the numbered slot
+    SaveState(u8),
+    /// Restore the emulator state from the numbered slot
+    LoadState(u8),
+    /// The rewind key is held, play recent frames backwards
+    Rewind,
     Exit,
 }
 
@@ -36,71 +90,596 @@ enum UserInput {
 /// What frontend to run the emulator with.
 enum Frontend {
     /// Run in native window
+    #[cfg(feature = "native")]
     Native,
     /// Run in terminal
+    #[cfg(feature = "terminal")]
     Terminal,
 }
 
 /// A chip-8 emulator that can run in a native window or directly in the terminal
 #[derive(Parser, Debug)]
-#[command()]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to a .ch8 file
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run a program, the same as leaving out the subcommand
+    Run(Box<RunArgs>),
+    /// Print a program as assembly instead of running it
+    Disasm(DisasmArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Path to a .ch8 file, or an Octo .8o source file to assemble
+    // only optional so that subcommands don't need it
+    #[arg(required = true)]
+    program: Option<PathBuf>,
+    #[arg(short, long, required_unless_present = "headless")]
+    frontend: Option<Frontend>,
+    /// Which interpreter's behavior to follow for ambiguous instructions.
+    /// One of vip, chip-48, schip or xo-chip, optionally followed by overrides
+    /// for individual quirks, e.g. `vip,clipping=off`.
+    /// Quirks: vf-reset, memory, display-wait, clipping, shifting, jumping.
+    /// The default is chip-48 with BNNN jumping to NNN plus V0, like earlier versions.
+    #[arg(short, long, default_value_t = Quirks::default())]
+    quirks: Quirks,
+    /// Address to load the program at, e.g. 0x600 for ETI-660 programs.
+    /// Memory below it is reserved for the interpreter.
+    #[arg(long, default_value = "0x200", value_parser = parse_address)]
+    load_addr: u16,
+    /// Address of the first instruction, if the program doesn't start where it's loaded
+    #[arg(long, value_parser = parse_address)]
+    entry: Option<u16>,
+    /// The 4x5 font for FX29: octo, vip, dream-6800 or eti-660,
+    /// or a file with the 5 bytes of each digit from 0 to F
+    #[arg(long, default_value = "octo")]
+    font: String,
+    /// The 8x10 font for FX30: octo or schip,
+    /// or a file with the 10 bytes of each digit from 0 to F
+    #[arg(long, default_value = "octo")]
+    big_font: String,
+    /// Address to store the fonts at, the small font followed by the big font
+    #[arg(long, default_value = "0x50", value_parser = parse_address)]
+    font_addr: u16,
+    /// How many seconds of play to keep for rewinding with the backspace key, 0 to turn rewinding off.
+    /// Headless runs never rewind.
+    #[arg(long, default_value_t = 10)]
+    rewind_seconds: u32,
+    /// Instructions to execute per second
+    #[arg(long, default_value_t = DEFAULT_INSTRUCTIONS_PER_SECOND, value_parser = clap::value_parser!(u32).range(1..))]
+    speed: u32,
+    /// How long instructions take: instructions (all the same, at --speed)
+    /// or vip (the machine cycles each takes on the COSMAC VIP, ignoring --speed)
+    #[arg(long, default_value = "instructions", value_parser = parse_timing)]
+    timing: Timing,
+    /// How many return addresses fit on the stack before a call fails:
+    /// a number, e.g. 12 like the COSMAC VIP or 16 like SUPER-CHIP, or unlimited
+    #[arg(long, default_value = "16", value_parser = parse_stack_depth)]
+    stack_depth: StackDepth,
+    /// What 0NNN calls to machine code routines do: trap (stop with an error) or ignore
+    #[arg(long, default_value = "trap", value_parser = parse_sys_calls)]
+    sys_calls: SysCallMode,
+    /// Seed for the random number generator, to make runs reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+    /// How to execute instructions: interpreter, cached (decode each instruction once)
+    /// or recompiler (translate runs of instructions into closures). They only differ in speed.
+    #[arg(long, default_value = "interpreter", value_parser = parse_engine)]
+    engine: Engine,
+    /// Run the program on an emulated COSMAC VIP with the original interpreter instead,
+    /// using this image of the VIP's monitor ROM. Hybrid programs can call 1802 machine code with 0NNN.
+    /// The debugger, tracing, save states and rewinding need the built-in interpreter,
+    /// and the options that configure it can't be used with this.
+    #[arg(
+        long,
+        requires = "vip_interpreter",
+        conflicts_with_all = [
+            "debug", "trace", "quirks", "engine", "speed", "timing", "load_addr", "entry", "font",
+            "big_font", "font_addr", "seed", "stack_depth", "sys_calls",
+        ],
+    )]
+    vip_rom: Option<PathBuf>,
+    /// The image of the CHIP-8 interpreter for --vip-rom, loaded at 0
+    #[arg(long, requires = "vip_rom")]
+    vip_interpreter: Option<PathBuf>,
+    /// Start paused in an interactive debugger that reads commands from stdin.
+    /// Requires the native frontend.
+    #[arg(long)]
+    debug: bool,
+    /// Write a line with the pc, opcode, registers and I, and with the default format
+    /// the mnemonic and timers, for every executed instruction to this file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// Only trace the instructions numbered start..end, counting from 0. Either end may be left out.
+    #[arg(long, requires = "trace", value_parser = parse_range)]
+    trace_range: Option<Range<u64>>,
+    /// How to lay out trace lines: chiprs, or cadmium to diff against traces from Cadmium
+    #[arg(long, requires = "trace", default_value = "chiprs", value_parser = parse_trace_format)]
+    trace_format: TraceFormat,
+    /// Run without a window or terminal, as fast as possible and with no keys pressed
+    #[arg(long, conflicts_with_all = ["frontend", "debug"], requires = "cycles")]
+    headless: bool,
+    /// Stop after executing this many instructions.
+    /// With --vip-rom they're 1802 instructions, and only checked after each frame.
+    #[arg(long, requires = "headless")]
+    cycles: Option<u64>,
+    /// Write the display to this .png or .pbm file when the run stops
+    #[arg(long, requires = "headless")]
+    out: Option<PathBuf>,
+    /// Write every Kth frame instead, numbering the files, e.g. frame-000120.png
+    #[arg(long, value_name = "K", requires = "out", value_parser = clap::value_parser!(u64).range(1..))]
+    capture_every: Option<u64>,
+    /// Write the frames where the display changed instead, numbering the files
+    #[arg(long, requires = "out")]
+    capture_updates: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DisasmArgs {
+    /// Path to a .ch8 file, or an Octo .8o source file to assemble
     program: PathBuf,
-    #[arg(short, long)]
-    frontend: Frontend,
+    /// octo for Octo source that can be assembled again, or classic for a listing with addresses
+    #[arg(short, long, default_value = "octo")]
+    syntax: Syntax,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
-    let program = match std::fs::read(&args.program) {
-        Ok(program) => program,
-        Err(e) => {
-            return Err(match e.kind() {
-                ErrorKind::NotFound => format!("{:?} does not exist.", &args.program),
-                ErrorKind::PermissionDenied => {
-                    format!("no read permissions for {:?}", &args.program)
-                }
-                _ => format!("{e}"),
-            }
-            .into());
-        }
+    match args.command {
+        Some(Command::Run(args)) => run(*args),
+        Some(Command::Disasm(args)) => disassemble(args),
+        None => run(args.run),
+    }
+}
+
+fn disassemble(args: DisasmArgs) -> Result<(), Box<dyn Error>> {
+    let program = read_program(&args.program)?;
+    print!("{}", Disassembly::new(&program).format(args.syntax));
+    Ok(())
+}
+
+/// Reads a .ch8 program, or assembles an Octo .8o source file
+fn read_program(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let bytes = std::fs::read(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => format!("{path:?} does not exist."),
+        ErrorKind::PermissionDenied => format!("no read permissions for {path:?}"),
+        _ => format!("{e}"),
+    })?;
+    if path.extension().is_some_and(|ext| ext == "8o") {
+        let source = String::from_utf8(bytes).map_err(|_| format!("{path:?} is not UTF-8"))?;
+        return assemble(&source).map_err(|e| format!("{}:{e}", path.display()).into());
+    }
+    Ok(bytes)
+}
+
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let Some(program_path) = &args.program else {
+        unreachable!("clap requires a program when there's no subcommand");
+    };
+    let program = read_program(program_path)?;
+    #[cfg(feature = "terminal")]
+    if args.debug && args.frontend == Some(Frontend::Terminal) {
+        return Err(
+            "--debug reads commands from the terminal, so it needs --frontend native".into(),
+        );
+    }
+    let mut debug_console = if args.debug {
+        Some(DebugConsole::new()?)
+    } else {
+        None
+    };
+    let mut capture = match &args.out {
+        Some(path) => Some(FrameCapture::new(
+            path.clone(),
+            args.capture_every,
+            args.capture_updates,
+        )?),
+        None => None,
     };
     let mut io_device: Box<dyn IODevice> = match args.frontend {
-        Frontend::Native => Box::new(NativeWindow::initialize()),
-        Frontend::Terminal => Box::new(TerminalWindow::initialize()),
+        _ if args.headless => Box::new(NullDevice),
+        #[cfg(feature = "native")]
+        Some(Frontend::Native) => Box::new(NativeWindow::initialize()),
+        #[cfg(feature = "terminal")]
+        Some(Frontend::Terminal) => Box::new(TerminalWindow::initialize()),
+        None => unreachable!("clap requires a frontend unless running headless"),
     };
-    let mut emulator = Chip8::load_program(&program);
+    if let (Some(rom), Some(interpreter)) = (&args.vip_rom, &args.vip_interpreter) {
+        let read =
+            |path: &PathBuf| std::fs::read(path).map_err(|e| format!("can't read {path:?}: {e}"));
+        let image = VipImage {
+            rom: &read(rom)?,
+            interpreter: &read(interpreter)?,
+            program: &program,
+        };
+        let mut vip = Vip::load(&image).map_err(|e| e.to_string())?;
+        return run_machine(&mut vip, program_path, io_device, capture, &args);
+    }
+    let image = ProgramImage {
+        start: args.load_addr,
+        entry: args.entry.unwrap_or(args.load_addr),
+        segments: &[(args.load_addr, &program)],
+        fonts: Fonts {
+            small: read_font(&args.font, SmallFont::named, SmallFont::from_bytes)?,
+            big: read_font(&args.big_font, BigFont::named, BigFont::from_bytes)?,
+            addr: args.font_addr,
+        },
+    };
+    let mut emulator = Chip8::load_image(&image, args.quirks).map_err(|e| e.to_string())?;
+    if let Some(seed) = args.seed {
+        emulator = emulator.with_seed(seed);
+    }
+    emulator = emulator
+        .with_engine(args.engine)
+        .with_speed(args.speed)
+        .with_timing(args.timing)
+        .with_stack_depth(args.stack_depth)
+        .with_sys_call_policy(match args.sys_calls {
+            SysCallMode::Ignore => SysCallPolicy::Ignore,
+            SysCallMode::Trap => SysCallPolicy::Trap,
+        });
+    if let Some(path) = &args.trace {
+        let range = args.trace_range.clone().unwrap_or(0..u64::MAX);
+        emulator = emulator.with_trace_hook(trace_writer(path, range, args.trace_format)?);
+    }
 
-    let mut inst_count = 0i64;
+    // saving a state every frame isn't free, so don't when nothing can rewind
+    let mut rewind_buffer = (!args.headless && args.rewind_seconds > 0)
+        .then(|| RewindBuffer::new((args.rewind_seconds * FRAMES_PER_SECOND) as usize));
+    let mut last_frame = Instant::now();
+    let mut next_frame = last_frame;
     loop {
-        let start_time = Instant::now();
+        let out_of_cycles = args
+            .cycles
+            .is_some_and(|cycles| emulator.instruction_count() >= cycles);
+        if emulator.has_exited() || out_of_cycles {
+            if let Some(capture) = &capture {
+                capture.finish(&emulator.display)?;
+            }
+            return Ok(());
+        }
         let pressed_keys = match io_device.poll_input() {
             UserInput::Exit => return Ok(()),
             UserInput::PressedKeys(pressed_keys) => pressed_keys,
+            UserInput::SaveState(slot) => {
+                std::fs::write(save_slot_path(program_path, slot), emulator.save_state())?;
+                continue;
+            }
+            UserInput::LoadState(slot) => {
+                match std::fs::read(save_slot_path(program_path, slot)) {
+                    Ok(state) => {
+                        emulator.restore_state(&state)?;
+                        io_device.render(&emulator.display)?;
+                    }
+                    // nothing has been saved to the slot yet
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+            UserInput::Rewind => {
+                if let Some(state) = rewind_buffer.as_mut().and_then(RewindBuffer::pop) {
+                    emulator.restore_state(&state)?;
+                    io_device.render(&emulator.display)?;
+                }
+                io_device.pause_beep();
+                sleep_until_next_frame(&mut next_frame);
+                last_frame = Instant::now();
+                continue;
+            }
+        };
+        if let Some(rewind_buffer) = &mut rewind_buffer {
+            rewind_buffer.push(emulator.save_state());
+        }
+        // headless runs don't wait for real time to pass, so every frame is exactly one frame long
+        let elapsed = if args.headless {
+            FRAME_TIME
+        } else {
+            last_frame.elapsed().min(MAX_FRAME_TIME)
         };
-        let mut display_updated = false;
-        for _ in 0..INSTRUCTIONS_PER_FRAME {
-            match emulator.step(pressed_keys) {
-                DisplayState::Updated => display_updated = true,
-                DisplayState::NotUpdated => {}
+        last_frame = Instant::now();
+        let mut quit = false;
+        let mut console_error = None;
+        let result = emulator.run_until(elapsed, pressed_keys, |emulator| {
+            if args
+                .cycles
+                .is_some_and(|cycles| emulator.instruction_count() >= cycles)
+            {
+                return true;
+            }
+            let Some(console) = debug_console.as_mut() else {
+                return false;
             };
-            inst_count = inst_count.wrapping_add(1);
+            // checking again after resuming lets the debugger skip the instruction it stopped at
+            while let Some(reason) = console.check(emulator) {
+                io_device.pause_beep();
+                let action = io_device
+                    .render(&emulator.display)
+                    .and_then(|()| Ok(console.prompt(&reason, emulator)?));
+                match action {
+                    Ok(ConsoleAction::Resume) => {}
+                    Ok(ConsoleAction::Quit) => quit = true,
+                    Err(e) => console_error = Some(e),
+                }
+                if quit || console_error.is_some() {
+                    return true;
+                }
+            }
+            false
+        });
+        if let Some(e) = console_error {
+            return Err(e);
+        }
+        if quit {
+            return Ok(());
+        }
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(err) => {
+                // restore the terminal before printing the report
+                drop(io_device);
+                eprint!("{}", chip8_crash_report(program_path, &err, &emulator));
+                // flushes the trace, which exit would skip
+                drop(emulator);
+                std::process::exit(1);
+            }
+        };
+        present(&emulator, &summary, io_device.as_mut(), &mut capture)?;
+        if !args.headless {
+            sleep_until_next_frame(&mut next_frame);
         }
-        if display_updated {
-            io_device.render(&emulator.display)?;
+    }
+}
+
+/// Runs an emulator other than [`Chip8`], which only needs to be shown and heard
+fn run_machine(
+    machine: &mut impl Machine,
+    program_path: &Path,
+    mut io_device: Box<dyn IODevice>,
+    mut capture: Option<FrameCapture>,
+    args: &RunArgs,
+) -> Result<(), Box<dyn Error>> {
+    let mut last_frame = Instant::now();
+    let mut next_frame = last_frame;
+    loop {
+        let out_of_cycles = args
+            .cycles
+            .is_some_and(|cycles| machine.instruction_count() >= cycles);
+        if machine.has_exited() || out_of_cycles {
+            if let Some(capture) = &capture {
+                capture.finish(machine.display())?;
+            }
+            return Ok(());
         }
-        emulator.tick_timers();
-        if emulator.is_sound_on() {
-            io_device.resume_beep();
+        let pressed_keys = match io_device.poll_input() {
+            UserInput::Exit => return Ok(()),
+            UserInput::PressedKeys(pressed_keys) => pressed_keys,
+            // save states and rewinding need the built-in interpreter
+            UserInput::SaveState(_) | UserInput::LoadState(_) | UserInput::Rewind => [false; 16],
+        };
+        let elapsed = if args.headless {
+            FRAME_TIME
         } else {
-            io_device.pause_beep();
+            last_frame.elapsed().min(MAX_FRAME_TIME)
+        };
+        last_frame = Instant::now();
+        let summary = match machine.run_for(elapsed, pressed_keys) {
+            Ok(summary) => summary,
+            Err(err) => {
+                // restore the terminal before printing the report
+                drop(io_device);
+                eprint!("{}", crash_report(program_path, &err, machine));
+                std::process::exit(1);
+            }
+        };
+        present(machine, &summary, io_device.as_mut(), &mut capture)?;
+        if !args.headless {
+            sleep_until_next_frame(&mut next_frame);
+        }
+    }
+}
+
+/// Renders and captures the display if it changed while running, and starts or stops the beep
+fn present(
+    machine: &impl Machine,
+    summary: &RunSummary,
+    io_device: &mut dyn IODevice,
+    capture: &mut Option<FrameCapture>,
+) -> Result<(), Box<dyn Error>> {
+    let display_updated = summary.display == DisplayState::Updated;
+    if display_updated {
+        io_device.render(machine.display())?;
+    }
+    if let Some(capture) = capture {
+        capture.frame(machine.display(), display_updated)?;
+    }
+    if machine.is_sound_on() {
+        io_device.resume_beep(machine.audio_pattern());
+    } else {
+        io_device.pause_beep();
+    }
+    Ok(())
+}
+
+/// Sleeps until the deadline for the next frame, which is one frame after the previous deadline
+/// so that the time spent emulating and rendering doesn't add up to drift.
+/// If the frame is already late, it starts the schedule over instead of hurrying to catch up.
+fn sleep_until_next_frame(next_frame: &mut Instant) {
+    *next_frame += FRAME_TIME;
+    let now = Instant::now();
+    if *next_frame > now {
+        std::thread::sleep(*next_frame - now);
+    } else {
+        *next_frame = now;
+    }
+}
+
+/// Returns a trace hook that writes the instructions numbered within `range` to a file.
+fn trace_writer(
+    path: &Path,
+    range: Range<u64>,
+    format: TraceFormat,
+) -> io::Result<impl FnMut(&TraceEntry)> {
+    let mut out = Some(BufWriter::new(File::create(path)?));
+    let mut inst_count = 0u64;
+    Ok(move |entry: &TraceEntry| {
+        if let (true, Some(writer)) = (range.contains(&inst_count), &mut out) {
+            if let Err(e) = writeln!(writer, "{}", entry.to_line(format)) {
+                eprintln!("chiprs: stopped writing the trace: {e}");
+                out = None;
+            }
         }
-        let elapsed_time = start_time.elapsed();
-        let time_between_frames = Duration::new(0, 1_000_000_000u32 / FRAMES_PER_SECOND);
-        if elapsed_time < time_between_frames {
-            let sleep_time = time_between_frames - elapsed_time;
-            std::thread::sleep(sleep_time);
+        inst_count += 1;
+    })
+}
+
+/// Looks up a built-in font by name, or reads one from a file
+fn read_font<F>(
+    name_or_path: &str,
+    named: fn(&str) -> Option<F>,
+    from_bytes: fn(&[u8]) -> Option<F>,
+) -> Result<F, Box<dyn Error>> {
+    if let Some(font) = named(name_or_path) {
+        return Ok(font);
+    }
+    let bytes = std::fs::read(name_or_path)
+        .map_err(|e| format!("{name_or_path:?} is not a built-in font or a readable file: {e}"))?;
+    from_bytes(&bytes).ok_or_else(|| {
+        format!(
+            "{name_or_path:?} is {} bytes, which is the wrong size for a font",
+            bytes.len()
+        )
+        .into()
+    })
+}
+
+/// Parses an address in hexadecimal with a 0x prefix, or in decimal
+fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("{s:?} is not an address between 0 and 0xFFFF"))
+}
+
+fn parse_engine(s: &str) -> Result<Engine, String> {
+    match s {
+        "interpreter" => Ok(Engine::Interpreter),
+        "cached" => Ok(Engine::CachedDecode),
+        "recompiler" => Ok(Engine::Recompiler),
+        _ => Err(format!(
+            "unknown engine {s:?}, expected one of interpreter, cached, recompiler"
+        )),
+    }
+}
+
+fn parse_timing(s: &str) -> Result<Timing, String> {
+    match s {
+        "instructions" => Ok(Timing::Instructions),
+        "vip" => Ok(Timing::Vip),
+        _ => Err(format!(
+            "unknown timing {s:?}, expected one of instructions, vip"
+        )),
+    }
+}
+
+fn parse_trace_format(s: &str) -> Result<TraceFormat, String> {
+    match s {
+        "chiprs" => Ok(TraceFormat::Chiprs),
+        "cadmium" => Ok(TraceFormat::Cadmium),
+        _ => Err(format!(
+            "unknown trace format {s:?}, expected one of chiprs, cadmium"
+        )),
+    }
+}
+
+fn parse_stack_depth(s: &str) -> Result<StackDepth, String> {
+    if s == "unlimited" {
+        return Ok(StackDepth::Unlimited);
+    }
+    match s.parse() {
+        Ok(depth) if depth > 0 => Ok(StackDepth::Limited(depth)),
+        _ => Err(format!(
+            "{s:?} is not a stack depth, expected a number of at least 1 or unlimited"
+        )),
+    }
+}
+
+/// The [`SysCallPolicy`]s that can be chosen on the command line, which can't register a handler
+#[derive(Debug, Clone, Copy)]
+enum SysCallMode {
+    Ignore,
+    Trap,
+}
+
+fn parse_sys_calls(s: &str) -> Result<SysCallMode, String> {
+    match s {
+        "ignore" => Ok(SysCallMode::Ignore),
+        "trap" => Ok(SysCallMode::Trap),
+        _ => Err(format!(
+            "unknown policy {s:?}, expected one of ignore, trap"
+        )),
+    }
+}
+
+/// Parses `start..end`, `start..` or `..end`
+fn parse_range(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("expected start..end, got {s:?}"))?;
+    let parse = |n: &str, default| match n {
+        "" => Ok(default),
+        n => n.parse().map_err(|_| format!("{n:?} is not a number")),
+    };
+    Ok(parse(start, 0)?..parse(end, u64::MAX)?)
+}
+
+/// Save states are stored next to the program, e.g. `game.ch8` saves slot 1 to `game.state1`
+fn save_slot_path(program: &Path, slot: u8) -> PathBuf {
+    program.with_extension(format!("state{slot}"))
+}
+
+/// Describes why the program stopped, for printing instead of a panic backtrace.
+/// Only the built-in interpreter can also show its stack, see [`chip8_crash_report`].
+fn crash_report(program: &Path, err: &Chip8Error, machine: &impl Machine) -> String {
+    let mut report = String::new();
+    writeln!(
+        report,
+        "chiprs: {program:?} crashed after {} instructions",
+        machine.instruction_count()
+    )
+    .unwrap();
+    writeln!(report, "  error:  {err}").unwrap();
+    if let (Some(pc), Some(opcode)) = (err.pc(), err.opcode()) {
+        writeln!(report, "  pc:     {pc:#06x}").unwrap();
+        writeln!(report, "  opcode: {opcode:#06x}").unwrap();
+    }
+    report
+}
+
+/// A [`crash_report`] followed by the call stack
+fn chip8_crash_report(program: &Path, err: &Chip8Error, emulator: &Chip8) -> String {
+    let mut report = crash_report(program, err, emulator);
+    // the most recent call first, like a backtrace
+    write!(report, "  stack: ").unwrap();
+    if emulator.stack().is_empty() {
+        write!(report, " empty").unwrap();
+    }
+    for addr in emulator.stack().iter().rev() {
+        write!(report, " {addr:#06x}").unwrap();
+    }
+    match emulator.stack_depth() {
+        StackDepth::Limited(depth) => {
+            writeln!(report, " ({} of {depth})", emulator.stack().len()).unwrap()
         }
+        StackDepth::Unlimited => writeln!(report).unwrap(),
     }
+    report
 }
@@ -3,6 +3,8 @@ extern crate sdl2;
 use crate::IODevice;
 use crate::UserInput;
 
+use chiprs::{AudioPattern, Display};
+
 use sdl2::audio::AudioDevice;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
@@ -12,11 +14,24 @@ use sdl2::{audio, event, EventPump};
 
 use std::error::Error;
 
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 320;
+/// Colors for each combination of the XO-CHIP bitplanes.
+/// Programs that only draw to the first plane are black and white.
+const PALETTE: [Color; 4] = [
+    Color::BLACK,
+    Color::WHITE,
+    Color::RGB(0xFF, 0x66, 0x00),
+    Color::RGB(0x66, 0x22, 0x00),
+];
+
 pub struct NativeWindow {
     canvas: Canvas<Window>,
     audio_device: AudioDevice<SquareWave>,
     event_pump: EventPump,
     pressed_keys: [bool; 16],
+    /// backspace is held
+    rewinding: bool,
 }
 
 impl NativeWindow {
@@ -24,7 +39,7 @@ impl NativeWindow {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
         let window = video_subsystem
-            .window("chip-8", 640, 320)
+            .window("chip-8", WINDOW_WIDTH, WINDOW_HEIGHT)
             .position_centered()
             .build()
             .expect("Unable to build sdl2 window");
@@ -38,9 +53,11 @@ impl NativeWindow {
             .open_playback(None, &desired_spec, |spec| {
                 // initialize the audio callback
                 SquareWave {
+                    freq: spec.freq as f32,
                     phase_inc: 440.0 / spec.freq as f32,
                     phase: 0.0,
                     volume: 0.25,
+                    pattern: None,
                 }
             })
             .unwrap();
@@ -57,6 +74,7 @@ impl NativeWindow {
             audio_device,
             event_pump,
             pressed_keys: [false; 16],
+            rewinding: false,
         }
     }
 }
@@ -96,6 +114,20 @@ impl IODevice for NativeWindow {
                 _ => None,
             }
         }
+        // F1-F4 save to slots 1-4, F5-F8 load from slots 1-4
+        fn key2slot(key: Keycode) -> Option<UserInput> {
+            match key {
+                Keycode::F1 => Some(UserInput::SaveState(1)),
+                Keycode::F2 => Some(UserInput::SaveState(2)),
+                Keycode::F3 => Some(UserInput::SaveState(3)),
+                Keycode::F4 => Some(UserInput::SaveState(4)),
+                Keycode::F5 => Some(UserInput::LoadState(1)),
+                Keycode::F6 => Some(UserInput::LoadState(2)),
+                Keycode::F7 => Some(UserInput::LoadState(3)),
+                Keycode::F8 => Some(UserInput::LoadState(4)),
+                _ => None,
+            }
+        }
         for event in self.event_pump.poll_iter() {
             match event {
                 event::Event::Quit { .. }
@@ -107,10 +139,17 @@ impl IODevice for NativeWindow {
                 }
                 event::Event::KeyDown {
                     keycode: Some(code),
+                    repeat,
                     ..
                 } => {
                     if let Some(chip8_key_code) = key2btn(code) {
                         self.pressed_keys[chip8_key_code] = true;
+                    } else if code == Keycode::Backspace {
+                        self.rewinding = true;
+                    } else if let Some(save_state_input) = key2slot(code) {
+                        if !repeat {
+                            return save_state_input;
+                        }
                     }
                 }
                 event::Event::KeyUp {
@@ -118,11 +157,16 @@ impl IODevice for NativeWindow {
                 } => {
                     if let Some(chip8_key_code) = key2btn(key) {
                         self.pressed_keys[chip8_key_code] = false;
+                    } else if key == Keycode::Backspace {
+                        self.rewinding = false;
                     }
                 }
                 _ => {}
             };
         }
+        if self.rewinding {
+            return UserInput::Rewind;
+        }
         UserInput::PressedKeys(self.pressed_keys)
     }
 
@@ -130,19 +174,32 @@ impl IODevice for NativeWindow {
         self.audio_device.pause();
     }
 
-    fn resume_beep(&mut self) {
+    fn resume_beep(&mut self, pattern: Option<AudioPattern>) {
+        {
+            let mut wave = self.audio_device.lock();
+            if wave.pattern != pattern {
+                wave.set_pattern(pattern);
+            }
+        }
         self.audio_device.resume();
     }
 
-    fn render(&mut self, display: &[[bool; 64]; 32]) -> Result<(), Box<dyn Error>> {
-        self.canvas.set_draw_color(Color::BLACK);
+    fn render(&mut self, display: &Display) -> Result<(), Box<dyn Error>> {
+        self.canvas.set_draw_color(PALETTE[0]);
         self.canvas.clear();
-        self.canvas.set_draw_color(Color::WHITE);
-        for (y, row) in display.iter().enumerate() {
-            for (x, pixel) in row.iter().enumerate() {
-                if *pixel {
-                    let white_box = sdl2::rect::Rect::new(x as i32 * 10, y as i32 * 10, 10, 10);
-                    self.canvas.fill_rect(white_box)?;
+        // the window is 640x320, so a pixel is 10x10 in low resolution and 5x5 in high resolution
+        let pixel_size = WINDOW_WIDTH / display.width() as u32;
+        for (y, row) in display.rows().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                if color != 0 {
+                    self.canvas.set_draw_color(PALETTE[color as usize]);
+                    let pixel_box = sdl2::rect::Rect::new(
+                        x as i32 * pixel_size as i32,
+                        y as i32 * pixel_size as i32,
+                        pixel_size,
+                        pixel_size,
+                    );
+                    self.canvas.fill_rect(pixel_box)?;
                 }
             }
         }
@@ -151,24 +208,52 @@ impl IODevice for NativeWindow {
     }
 }
 
+/// Plays a 440 Hz square wave, or the XO-CHIP audio pattern if the program loaded one
 struct SquareWave {
+    /// output samples per second
+    freq: f32,
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    pattern: Option<AudioPattern>,
+}
+
+impl SquareWave {
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+        self.phase = 0.0;
+        self.phase_inc = match pattern {
+            // the phase counts samples of the 128-sample pattern
+            Some(pattern) => pattern.sample_rate() / self.freq,
+            None => 440.0 / self.freq,
+        };
+    }
 }
 
 impl audio::AudioCallback for SquareWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        match self.pattern {
+            Some(AudioPattern { buffer, .. }) => {
+                for x in out.iter_mut() {
+                    let sample_idx = self.phase as usize;
+                    let sample = buffer[sample_idx / 8] & (0b1000_0000 >> (sample_idx % 8)) != 0;
+                    *x = if sample { self.volume } else { -self.volume };
+                    self.phase = (self.phase + self.phase_inc) % 128.0;
+                }
+            }
+            None => {
+                // Generate a square wave
+                for x in out.iter_mut() {
+                    *x = if self.phase <= 0.5 {
+                        self.volume
+                    } else {
+                        -self.volume
+                    };
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                }
+            }
         }
     }
 }
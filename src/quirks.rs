@@ -0,0 +1,223 @@
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String};
+#[cfg(feature = "alloc")]
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::str::FromStr;
+
+/// Toggles for the instructions whose behavior differs between CHIP-8 interpreters.
+///
+/// Start from one of the named presets and override individual flags with struct update syntax:
+/// `Quirks { jump_uses_vx: false, ..Quirks::schip() }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// FX55 and FX65 leave I pointing one past the last register that was stored or loaded
+    pub load_store_increments_index: bool,
+    /// DXYN waits for the next vertical blank, limiting the program to one sprite per frame.
+    /// Only [`Chip8::run_for`](crate::Chip8::run_for) and [`Chip8::run_until`](crate::Chip8::run_until)
+    /// know when that is, a caller of [`Chip8::step`](crate::Chip8::step) has to wait itself.
+    pub display_wait: bool,
+    /// Sprites drawn past the edge of the screen are clipped instead of wrapping around
+    pub clip_sprites: bool,
+    /// 8XY6 and 8XYE shift VX in place and ignore VY
+    pub shift_in_place: bool,
+    /// BNNN behaves as BXNN and jumps to XNN plus VX instead of NNN plus V0
+    pub jump_uses_vx: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the COSMAC VIP
+    pub const fn vip() -> Self {
+        Quirks {
+            logic_resets_vf: true,
+            load_store_increments_index: true,
+            display_wait: true,
+            clip_sprites: true,
+            shift_in_place: false,
+            jump_uses_vx: false,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const fn chip48() -> Self {
+        Quirks {
+            logic_resets_vf: false,
+            load_store_increments_index: false,
+            display_wait: false,
+            clip_sprites: true,
+            shift_in_place: true,
+            jump_uses_vx: true,
+        }
+    }
+
+    /// SUPER-CHIP 1.1, which behaves like CHIP-48 as far as these flags go.
+    /// Where the two differ, in how far FX55 and FX65 move I and in SUPER-CHIP only waiting
+    /// for the vertical blank in low resolution, neither matches a flag exactly.
+    pub const fn schip() -> Self {
+        Quirks::chip48()
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub const fn xo_chip() -> Self {
+        Quirks {
+            logic_resets_vf: false,
+            load_store_increments_index: true,
+            display_wait: false,
+            clip_sprites: false,
+            shift_in_place: false,
+            jump_uses_vx: false,
+        }
+    }
+
+    /// Sets a single flag by its short name, as accepted on the command line.
+    #[cfg(feature = "alloc")]
+    fn set(&mut self, name: &str, value: bool) -> Result<(), ParseQuirksError> {
+        let flag = match name {
+            "vf-reset" => &mut self.logic_resets_vf,
+            "memory" => &mut self.load_store_increments_index,
+            "display-wait" => &mut self.display_wait,
+            "clipping" => &mut self.clip_sprites,
+            "shifting" => &mut self.shift_in_place,
+            "jumping" => &mut self.jump_uses_vx,
+            _ => return Err(ParseQuirksError(format!("unknown quirk {name:?}"))),
+        };
+        *flag = value;
+        Ok(())
+    }
+
+    /// Every flag with its short name, as accepted on the command line
+    #[cfg(feature = "alloc")]
+    fn flags(&self) -> [(&'static str, bool); 6] {
+        [
+            ("vf-reset", self.logic_resets_vf),
+            ("memory", self.load_store_increments_index),
+            ("display-wait", self.display_wait),
+            ("clipping", self.clip_sprites),
+            ("shifting", self.shift_in_place),
+            ("jumping", self.jump_uses_vx),
+        ]
+    }
+}
+
+/// What this emulator did before quirks were configurable:
+/// CHIP-48, except that BNNN jumps to NNN plus V0 like on the COSMAC VIP.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            jump_uses_vx: false,
+            ..Quirks::chip48()
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseQuirksError(String);
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for ParseQuirksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl core::error::Error for ParseQuirksError {}
+
+/// Writes the preset that needs the fewest overrides followed by the overrides,
+/// in the form [`FromStr`] parses, e.g. `chip-48,jumping=off`.
+#[cfg(feature = "alloc")]
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let overrides = |preset: &Quirks| {
+            preset
+                .flags()
+                .into_iter()
+                .zip(self.flags())
+                .filter(|((_, preset_value), (_, value))| preset_value != value)
+                .map(|(_, flag)| flag)
+        };
+        let (name, preset) = [
+            ("vip", Quirks::vip()),
+            ("chip-48", Quirks::chip48()),
+            ("xo-chip", Quirks::xo_chip()),
+        ]
+        .into_iter()
+        .min_by_key(|(_, preset)| overrides(preset).count())
+        .unwrap();
+        f.write_str(name)?;
+        for (flag, value) in overrides(&preset) {
+            write!(f, ",{flag}={}", if value { "on" } else { "off" })?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a preset name optionally followed by per-flag overrides,
+/// e.g. `vip` or `schip,clipping=off,vf-reset=on`.
+#[cfg(feature = "alloc")]
+impl FromStr for Quirks {
+    type Err = ParseQuirksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let preset = parts.next().unwrap_or_default();
+        let mut quirks = match preset.to_ascii_lowercase().as_str() {
+            "vip" | "chip-8" | "chip8" => Quirks::vip(),
+            "chip-48" | "chip48" => Quirks::chip48(),
+            "schip" | "schip1.1" | "superchip" => Quirks::schip(),
+            "xo-chip" | "xochip" => Quirks::xo_chip(),
+            _ => {
+                return Err(ParseQuirksError(format!(
+                    "unknown quirks preset {preset:?}, expected one of vip, chip-48, schip, xo-chip"
+                )))
+            }
+        };
+        for part in parts {
+            let Some((name, value)) = part.split_once('=') else {
+                return Err(ParseQuirksError(format!(
+                    "expected a quirk override of the form name=on|off, got {part:?}"
+                )));
+            };
+            let value = match value {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                _ => {
+                    return Err(ParseQuirksError(format!(
+                        "expected on or off for quirk {name:?}, got {value:?}"
+                    )))
+                }
+            };
+            quirks.set(name, value)?;
+        }
+        Ok(quirks)
+    }
+}
+
+#[test]
+fn test_parse_quirks() {
+    assert_eq!("vip".parse(), Ok(Quirks::vip()));
+    assert_eq!("XO-CHIP".parse(), Ok(Quirks::xo_chip()));
+    assert_eq!("chip-48,jumping=off".parse(), Ok(Quirks::default()));
+    assert_eq!(Quirks::default().to_string(), "chip-48,jumping=off");
+    assert_eq!(Quirks::xo_chip().to_string(), "xo-chip");
+    let quirks = Quirks {
+        logic_resets_vf: false,
+        ..Quirks::vip()
+    };
+    assert_eq!(quirks.to_string(), "vip,vf-reset=off");
+    assert_eq!(quirks.to_string().parse(), Ok(quirks));
+    assert_eq!(
+        "schip,clipping=off,vf-reset=on".parse(),
+        Ok(Quirks {
+            clip_sprites: false,
+            logic_resets_vf: true,
+            ..Quirks::schip()
+        })
+    );
+    assert!("cosmac".parse::<Quirks>().is_err());
+    assert!("vip,clipping".parse::<Quirks>().is_err());
+    assert!("vip,wrapping=on".parse::<Quirks>().is_err());
+}
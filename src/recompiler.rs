@@ -0,0 +1,379 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::cached::AddressMap;
+use crate::disasm::Instruction;
+use crate::{Chip8, Chip8Error, DisplayState, MEMORY_SIZE};
+
+/// Blocks are cut short after this many instructions
+const MAX_BLOCK_LEN: usize = 32;
+
+/// The most bytes a block can cover, if every instruction is the 4-byte F000 NNNN
+const MAX_BLOCK_BYTES: usize = MAX_BLOCK_LEN * 4;
+
+/// One instruction, with its operands and the quirks that apply to it already filled in
+type Op = Box<dyn Fn(&mut Chip8, [bool; 16]) -> Result<DisplayState, Chip8Error>>;
+
+/// Instructions that run one after another, ending at the first one that may not continue to the next,
+/// e.g. a jump or a skip, or at a draw so that frontends that wait for the display get control back
+struct Block {
+    /// The address of each instruction and its translation
+    ops: Vec<(u16, Op)>,
+    /// One past the last byte read while translating the block
+    end: usize,
+}
+
+/// The translated blocks by start address, and where [`Chip8::step`] is in the current one
+#[derive(Default)]
+pub(crate) struct Blocks {
+    blocks: AddressMap<Rc<Block>>,
+    /// The block the last step executed an instruction of, and the index of the next instruction in it
+    cursor: Option<(Rc<Block>, usize)>,
+    /// Counts invalidations, so that a step can tell whether the instruction it executed wrote over its own block
+    generation: u32,
+    /// How many times [`Chip8::run_blocks`] has started on a block
+    #[cfg(test)]
+    runs: u64,
+}
+
+impl Blocks {
+    /// Forgets the blocks that overlap the written bytes
+    pub(crate) fn invalidate(&mut self, written: Range<usize>) {
+        if self.blocks.is_empty() {
+            return;
+        }
+        // blocks don't wrap around the end of memory
+        for start in written.start.saturating_sub(MAX_BLOCK_BYTES - 1)..written.end {
+            if self
+                .blocks
+                .get(start)
+                .is_some_and(|block| block.end > written.start)
+            {
+                self.blocks.remove(start);
+                self.cursor = None;
+                self.generation = self.generation.wrapping_add(1);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+        self.cursor = None;
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
+
+impl Chip8 {
+    /// Executes the instruction at pc with [`crate::Engine::Recompiler`]
+    pub(crate) fn step_recompiled(
+        &mut self,
+        pressed_keys: [bool; 16],
+    ) -> Result<DisplayState, Chip8Error> {
+        let (block, idx) = self.current_block();
+        let generation = self.blocks.generation;
+        let result = (block.ops[idx].1)(self, pressed_keys);
+        if idx + 1 < block.ops.len() && self.blocks.generation == generation {
+            self.blocks.cursor = Some((block, idx + 1));
+        }
+        result
+    }
+
+    /// Executes instructions a block at a time for [`Chip8::run_until`], with the tracing and counting of
+    /// [`Chip8::step`] and taking each out of the credit, and calls `proceed` before each after the first.
+    /// Nothing else happens between the instructions of a block, the next block is only looked up once the program
+    /// leaves this one, e.g. at a jump or after a store over it.
+    /// Stops after an instruction that updates the display or exits, and returns how many executed and the result of the last.
+    pub(crate) fn run_blocks(
+        &mut self,
+        pressed_keys: [bool; 16],
+        mut proceed: impl FnMut(&mut Chip8) -> bool,
+    ) -> (u64, Result<DisplayState, Chip8Error>) {
+        let mut executed = 0;
+        loop {
+            let (block, mut idx) = self.current_block();
+            #[cfg(test)]
+            {
+                self.blocks.runs += 1;
+            }
+            let generation = self.blocks.generation;
+            loop {
+                let cycles = self.cycles;
+                let result = self.run_instruction(|chip8| (block.ops[idx].1)(chip8, pressed_keys));
+                if result.is_err() {
+                    return (executed, result);
+                }
+                executed += 1;
+                self.charge(cycles);
+                if result != Ok(DisplayState::NotUpdated) || self.exited {
+                    return (executed, result);
+                }
+                idx += 1;
+                let in_block = idx < block.ops.len()
+                    && self.pc == block.ops[idx].0
+                    && self.blocks.generation == generation;
+                if !proceed(self) {
+                    if in_block {
+                        self.blocks.cursor = Some((block, idx));
+                    }
+                    return (executed, result);
+                }
+                if !in_block {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The block to continue with at pc and the index of pc in it,
+    /// which is where the last step left off or the start of a block
+    fn current_block(&mut self) -> (Rc<Block>, usize) {
+        let pc = self.pc;
+        match self.blocks.cursor.take() {
+            Some((block, idx)) if block.ops[idx].0 == pc => (block, idx),
+            _ => {
+                let block = match self.blocks.blocks.get(pc as usize) {
+                    Some(block) => Rc::clone(block),
+                    None => {
+                        let block = Rc::new(self.translate(pc));
+                        self.blocks.blocks.insert(pc as usize, Rc::clone(&block));
+                        block
+                    }
+                };
+                (block, 0)
+            }
+        }
+    }
+
+    /// Translates the block of instructions starting at `start`
+    fn translate(&self, start: u16) -> Block {
+        let mut ops = Vec::new();
+        let mut addr = start as usize;
+        loop {
+            // an instruction that wraps around the end of memory is left to the interpreter
+            if addr + 4 > MEMORY_SIZE {
+                ops.push((
+                    addr as u16,
+                    Box::new(|chip8: &mut Chip8, pressed_keys| chip8.interpret(pressed_keys)) as Op,
+                ));
+                addr = MEMORY_SIZE;
+                break;
+            }
+            let pc = addr as u16;
+            let opcode = self.read_u16(pc);
+            let inst = Instruction::decode(opcode, self.read_u16(pc + 2));
+            let len = if matches!(inst, Instruction::SetIndexLong(_)) {
+                4
+            } else {
+                2
+            };
+            ops.push((pc, self.translate_instruction(pc, opcode, inst)));
+            addr += len;
+            if ends_block(inst) || ops.len() == MAX_BLOCK_LEN {
+                break;
+            }
+        }
+        Block { ops, end: addr }
+    }
+
+    /// Translates the common instructions that are cheap to execute, and leaves the rest to [`Chip8::execute`]
+    fn translate_instruction(&self, pc: u16, opcode: u16, inst: Instruction) -> Op {
+        use Instruction::*;
+        let next = pc + 2;
+        let reg = |x: u8| x as usize;
+        let logic_resets_vf = self.quirks.logic_resets_vf;
+        let shift_in_place = self.quirks.shift_in_place;
+        match inst {
+            Jump(addr) => Box::new(move |chip8, _| {
+                chip8.pc = addr;
+                Ok(DisplayState::NotUpdated)
+            }),
+            Call(addr) => Box::new(move |chip8, _| {
+                chip8.pc = next;
+                if !chip8.stack.push(next) {
+                    return Err(Chip8Error::StackOverflow { pc, opcode });
+                }
+                chip8.pc = addr;
+                Ok(DisplayState::NotUpdated)
+            }),
+            Return => Box::new(move |chip8, _| {
+                chip8.pc = next;
+                chip8.pc = chip8
+                    .stack
+                    .pop()
+                    .ok_or(Chip8Error::StackUnderflow { pc, opcode })?;
+                Ok(DisplayState::NotUpdated)
+            }),
+            SkipIfEqual { x, value } => skip(next, move |chip8| chip8.registers[reg(x)] == value),
+            SkipIfNotEqual { x, value } => {
+                skip(next, move |chip8| chip8.registers[reg(x)] != value)
+            }
+            SkipIfRegistersEqual { x, y } => skip(next, move |chip8| {
+                chip8.registers[reg(x)] == chip8.registers[reg(y)]
+            }),
+            SkipIfRegistersNotEqual { x, y } => skip(next, move |chip8| {
+                chip8.registers[reg(x)] != chip8.registers[reg(y)]
+            }),
+            Set { x, value } => simple(next, move |chip8| chip8.registers[reg(x)] = value),
+            Add { x, value } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] = chip8.registers[reg(x)].wrapping_add(value)
+            }),
+            Copy { x, y } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] = chip8.registers[reg(y)]
+            }),
+            Or { x, y } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] |= chip8.registers[reg(y)];
+                if logic_resets_vf {
+                    chip8.registers[0xF] = 0;
+                }
+            }),
+            And { x, y } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] &= chip8.registers[reg(y)];
+                if logic_resets_vf {
+                    chip8.registers[0xF] = 0;
+                }
+            }),
+            Xor { x, y } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] ^= chip8.registers[reg(y)];
+                if logic_resets_vf {
+                    chip8.registers[0xF] = 0;
+                }
+            }),
+            AddRegister { x, y } => simple(next, move |chip8| {
+                let (sum, carry) = chip8.registers[reg(x)].overflowing_add(chip8.registers[reg(y)]);
+                chip8.registers[reg(x)] = sum;
+                chip8.registers[0xF] = carry as u8;
+            }),
+            Sub { x, y } => simple(next, move |chip8| {
+                let (vx, vy) = (chip8.registers[reg(x)], chip8.registers[reg(y)]);
+                chip8.registers[reg(x)] = vx.wrapping_sub(vy);
+                chip8.registers[0xF] = (vx >= vy) as u8;
+            }),
+            SubReversed { x, y } => simple(next, move |chip8| {
+                let (vx, vy) = (chip8.registers[reg(x)], chip8.registers[reg(y)]);
+                chip8.registers[reg(x)] = vy.wrapping_sub(vx);
+                chip8.registers[0xF] = (vy >= vx) as u8;
+            }),
+            ShiftRight { x, y } => {
+                let src = if shift_in_place { x } else { y };
+                simple(next, move |chip8| {
+                    let value = chip8.registers[reg(src)];
+                    chip8.registers[reg(x)] = value >> 1;
+                    chip8.registers[0xF] = value & 0x1;
+                })
+            }
+            ShiftLeft { x, y } => {
+                let src = if shift_in_place { x } else { y };
+                simple(next, move |chip8| {
+                    let value = chip8.registers[reg(src)];
+                    chip8.registers[reg(x)] = value << 1;
+                    chip8.registers[0xF] = value >> 7;
+                })
+            }
+            SetIndex(addr) => simple(next, move |chip8| chip8.index_reg = addr),
+            AddIndex(x) => simple(next, move |chip8| {
+                let (index, overflow) = chip8
+                    .index_reg
+                    .overflowing_add(chip8.registers[reg(x)] as u16);
+                chip8.index_reg = index;
+                if overflow {
+                    chip8.registers[0xF] = 1;
+                }
+            }),
+            Random { x, mask } => simple(next, move |chip8| {
+                chip8.registers[reg(x)] = chip8.rng.next_u8() & mask
+            }),
+            GetDelay(x) => simple(next, move |chip8| {
+                chip8.registers[reg(x)] = chip8.delay_timer
+            }),
+            SetDelay(x) => simple(next, move |chip8| {
+                chip8.delay_timer = chip8.registers[reg(x)]
+            }),
+            SetSound(x) => simple(next, move |chip8| {
+                chip8.sound_timer = chip8.registers[reg(x)]
+            }),
+            _ => Box::new(move |chip8, pressed_keys| chip8.execute(pc, opcode, inst, pressed_keys)),
+        }
+    }
+}
+
+/// An instruction that can't fail or update the display, and continues to the next one
+fn simple(next: u16, f: impl Fn(&mut Chip8) + 'static) -> Op {
+    Box::new(move |chip8, _| {
+        chip8.pc = next;
+        f(chip8);
+        Ok(DisplayState::NotUpdated)
+    })
+}
+
+/// A conditional skip of the instruction at `next`
+fn skip(next: u16, condition: impl Fn(&Chip8) -> bool + 'static) -> Op {
+    Box::new(move |chip8, _| {
+        chip8.pc = next;
+        if condition(chip8) {
+            // the next instruction isn't part of the block, so its length is read when skipping it
+            chip8.skip_instruction();
+        }
+        Ok(DisplayState::NotUpdated)
+    })
+}
+
+/// Whether an instruction may not continue to the one after it, or should return control to the frontend
+fn ends_block(inst: Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        inst,
+        Jump(_)
+            | Call(_)
+            | Return
+            | JumpOffset(_)
+            | SkipIfEqual { .. }
+            | SkipIfNotEqual { .. }
+            | SkipIfRegistersEqual { .. }
+            | SkipIfRegistersNotEqual { .. }
+            | SkipIfKey(_)
+            | SkipIfNotKey(_)
+            | WaitKey(_)
+            | Draw { .. }
+            | Exit
+            | MachineCall(_)
+            | Unknown(_)
+    )
+}
+
+#[test]
+fn test_run_for_runs_whole_blocks() {
+    use crate::{Engine, Quirks, TIMER_PERIOD};
+
+    // loop: v0 += 1, v1 += 2, v2 += 3, v3 += 4, jump loop
+    let program = [0x70, 0x01, 0x71, 0x02, 0x72, 0x03, 0x73, 0x04, 0x12, 0x00];
+    let load = |engine| {
+        Chip8::load_program(&program, Quirks::default())
+            .unwrap()
+            .with_seed(0)
+            .with_speed(1200)
+            .with_engine(engine)
+    };
+    let mut reference = load(Engine::Interpreter);
+    let mut emulator = load(Engine::Recompiler);
+    for _ in 0..60 {
+        let summary = emulator.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+        assert_eq!(
+            summary,
+            reference.run_for(TIMER_PERIOD, [false; 16]).unwrap()
+        );
+        assert_eq!(summary.instructions, 20);
+    }
+    assert!(emulator.save_state() == reference.save_state());
+    // the 20 instructions of each frame are the loop 4 times, so 4 blocks
+    assert_eq!(emulator.blocks.runs, 4 * 60);
+
+    // a frame that ends partway through the block continues in it:
+    // 42 instructions start the block 9 times, and 4 of the 5 frames after the first start partway through
+    let mut emulator = load(Engine::Recompiler).with_speed(420);
+    emulator.run_for(TIMER_PERIOD * 6, [false; 16]).unwrap();
+    assert_eq!(emulator.instruction_count(), 42);
+    assert_eq!(emulator.blocks.runs, 9 + 4);
+    assert!((0x202..0x20A).all(|addr| emulator.blocks.blocks.get(addr).is_none()));
+}
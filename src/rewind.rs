@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+/// A ring buffer of the most recent save states, for stepping the emulator backwards in time.
+///
+/// Only the newest state is kept whole. Every older state is stored as the XOR of itself with the
+/// state that followed it, run-length encoded. Consecutive frames differ in a handful of bytes,
+/// so each delta is a few bytes rather than the size of the full 64 KiB address space.
+pub struct RewindBuffer {
+    /// maximum number of states to remember
+    capacity: usize,
+    /// deltas to get from each state to the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    /// the most recently pushed state
+    latest: Option<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity,
+            deltas: VecDeque::new(),
+            latest: None,
+        }
+    }
+
+    /// Records a new state, forgetting the oldest one if the buffer is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(prev) = self.latest.replace(state) {
+            let delta = encode_delta(&prev, self.latest.as_ref().unwrap());
+            self.deltas.push_back(delta);
+        }
+        if self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Removes and returns the most recent state.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self
+            .deltas
+            .pop_back()
+            .map(|delta| decode_delta(&latest, &delta));
+        Some(latest)
+    }
+}
+
+/// Encodes `older` relative to `newer`:
+/// the length of `older`, then runs of (number of unchanged bytes, number of changed bytes, XOR of the changed bytes).
+/// The shorter state is treated as if it were padded with zeros.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let xor = |idx: usize| older.get(idx).unwrap_or(&0) ^ newer.get(idx).unwrap_or(&0);
+    let mut out = Vec::new();
+    write_varint(&mut out, older.len());
+    let mut idx = 0;
+    while idx < len {
+        let unchanged_start = idx;
+        while idx < len && xor(idx) == 0 {
+            idx += 1;
+        }
+        let changed_start = idx;
+        while idx < len && xor(idx) != 0 {
+            idx += 1;
+        }
+        write_varint(&mut out, changed_start - unchanged_start);
+        write_varint(&mut out, idx - changed_start);
+        out.extend((changed_start..idx).map(xor));
+    }
+    out
+}
+
+/// Reconstructs the older state from the newer state and the delta produced by `encode_delta`.
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta;
+    let older_len = read_varint(&mut delta);
+    let mut older = newer.to_vec();
+    older.resize(older_len.max(newer.len()), 0);
+    let mut idx = 0;
+    while !delta.is_empty() {
+        idx += read_varint(&mut delta);
+        let changed_len = read_varint(&mut delta);
+        let (changes, rest) = delta.split_at(changed_len);
+        for (byte, change) in older[idx..idx + changed_len].iter_mut().zip(changes) {
+            *byte ^= change;
+        }
+        idx += changed_len;
+        delta = rest;
+    }
+    older.truncate(older_len);
+    older
+}
+
+/// LEB128: 7 bits per byte, with the high bit set on every byte but the last
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[0];
+        *bytes = &bytes[1..];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[test]
+fn test_rewind_buffer() {
+    let mut buffer = RewindBuffer::new(3);
+    let states = [
+        vec![0u8; 300],
+        [vec![0u8; 200], vec![1u8; 100]].concat(),
+        vec![7u8; 2],
+        vec![1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 5],
+    ];
+    for state in &states {
+        buffer.push(state.clone());
+    }
+    // the first state was forgotten to make room for the last one
+    assert_eq!(buffer.pop().as_ref(), Some(&states[3]));
+    assert_eq!(buffer.pop().as_ref(), Some(&states[2]));
+    assert_eq!(buffer.pop().as_ref(), Some(&states[1]));
+    assert_eq!(buffer.pop(), None);
+}
@@ -0,0 +1,374 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::random::{RandomSource, SplitMix64};
+use crate::stack::Stack;
+use crate::{
+    BigFont, Chip8, Display, Fonts, SmallFont, HIRES_HEIGHT, HIRES_WIDTH, MEMORY_SIZE, PLANE_COUNT,
+    TIMER_PERIOD,
+};
+
+/// Identifies a chiprs save state
+const MAGIC: &[u8; 4] = b"CH8S";
+/// Incremented whenever the layout below changes
+/// Version 2 added the random number generator state
+/// Version 3 added the fonts
+/// Version 4 added how far [`Chip8::run_for`] is into the current frame and the machine cycle count
+const VERSION: u8 = 4;
+
+/// Reasons a save state can't be restored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the save state header
+    NotASaveState,
+    /// The save state was written by a different version of the format
+    UnsupportedVersion(u8),
+    /// The data ends before the save state does
+    Truncated,
+    /// A field holds a value the emulator can't be in
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotASaveState => f.write_str("not a chiprs save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {version} is not supported, expected version {VERSION} or older"
+            ),
+            StateError::Truncated => f.write_str("save state is truncated"),
+            StateError::Corrupt(field) => write!(f, "save state has an invalid {field}"),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+impl Chip8 {
+    /// Serializes the full machine state into a versioned binary format.
+    ///
+    /// The layout is the magic bytes `CH8S` and a version byte, followed by
+    /// memory, display, pc, I, stack, timers, registers, the SUPER-CHIP and XO-CHIP state,
+    /// the random number generator, the fonts' address and digits,
+    /// the nanoseconds since the last timer tick, instruction credit and display wait of [`Chip8::run_for`],
+    /// and the machine cycles counted with [`Timing::Vip`](crate::Timing::Vip).
+    /// 16-bit values are big-endian. Quirks are configuration rather than state and aren't included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MEMORY_SIZE + HIRES_WIDTH * HIRES_HEIGHT + 128);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.memory);
+        out.push(self.display.hires as u8);
+        for row in &self.display.pixels {
+            out.extend_from_slice(row);
+        }
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.extend_from_slice(&self.index_reg.to_be_bytes());
+        out.extend_from_slice(&(self.stack().len() as u16).to_be_bytes());
+        for addr in self.stack.as_slice() {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.flags);
+        out.push(self.exited as u8);
+        out.push(self.selected_planes);
+        match self.audio_buffer {
+            Some(buffer) => {
+                out.push(1);
+                out.extend_from_slice(&buffer);
+            }
+            None => out.push(0),
+        }
+        out.push(self.pitch);
+        match &self.rng {
+            RandomSource::Seeded(rng) => {
+                out.push(0);
+                out.extend_from_slice(&rng.state.to_be_bytes());
+            }
+            RandomSource::Custom(_) => out.push(1),
+        }
+        out.extend_from_slice(&self.fonts.addr.to_be_bytes());
+        out.extend_from_slice(self.fonts.small.as_bytes());
+        out.extend_from_slice(self.fonts.big.as_bytes());
+        out.extend_from_slice(&self.clock.since_tick.to_be_bytes());
+        out.extend_from_slice(&self.clock.credit.to_be_bytes());
+        out.push(self.clock.waiting_for_tick as u8);
+        out.extend_from_slice(&self.cycles.to_be_bytes());
+        out
+    }
+
+    /// Restores a state produced by [`Chip8::save_state`].
+    /// The emulator is left unchanged if the state can't be read.
+    /// A custom random number generator is kept, since its state can't be saved.
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader { bytes: state };
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(StateError::NotASaveState);
+        }
+        let version = reader.u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut memory = [0u8; MEMORY_SIZE];
+        memory.copy_from_slice(reader.take(MEMORY_SIZE)?);
+        let mut display = Display {
+            hires: reader.bool("display resolution")?,
+            ..Display::default()
+        };
+        for row in &mut display.pixels {
+            row.copy_from_slice(reader.take(HIRES_WIDTH)?);
+        }
+        let pc = reader.u16()?;
+        let index_reg = reader.u16()?;
+        let stack_len = reader.u16()?;
+        // the configured depth isn't part of the state
+        let mut stack = Stack::new(self.stack.depth);
+        for _ in 0..stack_len {
+            if !stack.push(reader.u16()?) {
+                return Err(StateError::Corrupt("stack"));
+            }
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let mut registers = [0u8; 16];
+        registers.copy_from_slice(reader.take(16)?);
+        let mut flags = [0u8; 16];
+        flags.copy_from_slice(reader.take(16)?);
+        let exited = reader.bool("exit flag")?;
+        let selected_planes = reader.u8()?;
+        if selected_planes > (1 << PLANE_COUNT) - 1 {
+            return Err(StateError::Corrupt("plane selection"));
+        }
+        let audio_buffer = match reader.bool("audio buffer flag")? {
+            true => {
+                let mut buffer = [0u8; 16];
+                buffer.copy_from_slice(reader.take(16)?);
+                Some(buffer)
+            }
+            false => None,
+        };
+        let pitch = reader.u8()?;
+        // None keeps the current generator
+        let rng = match version {
+            1 => None,
+            _ => match reader.u8()? {
+                0 => {
+                    let bytes = reader.take(8)?;
+                    let state = u64::from_be_bytes(bytes.try_into().unwrap());
+                    Some(SplitMix64 { state })
+                }
+                1 => None,
+                _ => return Err(StateError::Corrupt("random number generator")),
+            },
+        };
+        // older states were all saved with the default fonts
+        let fonts = match version {
+            1 | 2 => Fonts::default(),
+            _ => {
+                let addr = reader.u16()?;
+                // take returns exactly the size of each font, so they always parse
+                let small = SmallFont::from_bytes(reader.take(SmallFont::SIZE)?).unwrap();
+                let big = BigFont::from_bytes(reader.take(BigFont::SIZE)?).unwrap();
+                Fonts { small, big, addr }
+            }
+        };
+        if fonts.range().end > MEMORY_SIZE {
+            return Err(StateError::Corrupt("font address"));
+        }
+        // None keeps the current run's position in the frame and cycle count
+        let clock = match version {
+            1..=3 => None,
+            _ => {
+                let since_tick = reader.u64()?;
+                let credit = reader.u64()? as i64;
+                let waiting_for_tick = reader.bool("display wait flag")?;
+                if since_tick >= TIMER_PERIOD.as_nanos() as u64 {
+                    return Err(StateError::Corrupt("time since the last timer tick"));
+                }
+                let cycles = reader.u64()?;
+                Some((since_tick, credit, waiting_for_tick, cycles))
+            }
+        };
+        if !reader.bytes.is_empty() {
+            return Err(StateError::Corrupt("length"));
+        }
+
+        self.memory = memory;
+        self.clear_code();
+        self.display = display;
+        self.pc = pc;
+        self.index_reg = index_reg;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        self.flags = flags;
+        self.exited = exited;
+        self.selected_planes = selected_planes;
+        self.audio_buffer = audio_buffer;
+        self.pitch = pitch;
+        self.fonts = fonts;
+        if let Some(rng) = rng {
+            self.rng = RandomSource::Seeded(rng);
+        }
+        if let Some((since_tick, credit, waiting_for_tick, cycles)) = clock {
+            self.clock.since_tick = since_tick;
+            self.clock.credit = credit;
+            self.clock.waiting_for_tick = waiting_for_tick;
+            self.cycles = cycles;
+        }
+        Ok(())
+    }
+}
+
+/// Consumes a save state from front to back
+struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt(field)),
+        }
+    }
+}
+
+#[test]
+fn test_save_and_restore_state() {
+    use crate::Quirks;
+
+    // v0 := 5, call 0x206, (0x204) jump 0x204, (0x206) i := font character v0, draw 8x5 at (v0, v0),
+    // v1 := random 0xFF, jump 0x20A
+    let program = [
+        0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0xF0, 0x29, 0xD0, 0x05, 0xC1, 0xFF, 0x12, 0x0A,
+    ];
+    let mut emulator = Chip8::load_program(&program, Quirks::default()).unwrap();
+    for _ in 0..4 {
+        emulator.step([false; 16]).unwrap();
+    }
+    let state = emulator.save_state();
+
+    let mut restored = Chip8::load_program(&[], Quirks::default()).unwrap();
+    restored.restore_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.display, emulator.display);
+    assert_eq!(restored.pc, 0x20A);
+    assert_eq!(restored.stack(), [0x204]);
+    assert_eq!(restored.registers[0], 5);
+    // both continue with the same random numbers
+    emulator.step([false; 16]).unwrap();
+    restored.step([false; 16]).unwrap();
+    assert_eq!(restored.registers[1], emulator.registers[1]);
+    assert_eq!(restored.save_state(), emulator.save_state());
+
+    assert_eq!(
+        restored.restore_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    assert_eq!(
+        restored.restore_state(b"not a save state"),
+        Err(StateError::NotASaveState)
+    );
+    let mut future_state = state.clone();
+    future_state[4] = VERSION + 1;
+    assert_eq!(
+        restored.restore_state(&future_state),
+        Err(StateError::UnsupportedVersion(VERSION + 1))
+    );
+    // after the magic, version, memory, display, pc, i, one stack entry, timers, registers,
+    // flags and exit flag
+    let planes =
+        4 + 1 + MEMORY_SIZE + 1 + HIRES_WIDTH * HIRES_HEIGHT + 2 + 2 + 2 + 2 + 2 + 16 + 16 + 1;
+    assert_eq!(state[planes], emulator.selected_planes);
+    let mut bad_planes = state.clone();
+    bad_planes[planes] = 1 << PLANE_COUNT;
+    assert_eq!(
+        restored.restore_state(&bad_planes),
+        Err(StateError::Corrupt("plane selection"))
+    );
+    // the failed restores didn't change anything
+    assert_eq!(restored.save_state(), emulator.save_state());
+
+    // i := font character v0, at the font's own address
+    let fonts = Fonts {
+        small: SmallFont::VIP,
+        big: BigFont::SCHIP,
+        addr: 0x1000,
+    };
+    let image = crate::ProgramImage {
+        segments: &[(0x200, &[0xF0, 0x29])],
+        fonts,
+        ..Default::default()
+    };
+    let mut emulator = Chip8::load_image(&image, Quirks::default()).unwrap();
+    emulator.registers[0] = 1;
+    emulator.step([false; 16]).unwrap();
+    restored.restore_state(&emulator.save_state()).unwrap();
+    assert_eq!(*restored.fonts(), fonts);
+    assert_eq!(restored.index_reg, 0x1005);
+}
+
+#[test]
+fn test_restore_run_for_clock() {
+    use core::time::Duration;
+
+    use crate::{Quirks, Timing};
+
+    // loop: draw, v0 += 1, jump loop
+    let program = [0xD0, 0x01, 0x70, 0x01, 0x12, 0x00];
+    for timing in [Timing::Instructions, Timing::Vip] {
+        let load = |program: &[u8]| {
+            Chip8::load_program(program, Quirks::vip())
+                .unwrap()
+                .with_speed(700)
+                .with_timing(timing)
+        };
+        let mut emulator = load(&program);
+        // part way into a frame, waiting for the tick after a draw and with part of an instruction's credit
+        emulator
+            .run_for(Duration::from_micros(20_700), [false; 16])
+            .unwrap();
+        assert!(emulator.clock.waiting_for_tick);
+        let state = emulator.save_state();
+
+        let mut restored = load(&[]);
+        restored.restore_state(&state).unwrap();
+        assert_eq!(restored.cycles(), emulator.cycles());
+        for micros in [1_000, 12_345, 16_667, 50_000] {
+            let duration = Duration::from_micros(micros);
+            assert_eq!(
+                restored.run_for(duration, [false; 16]),
+                emulator.run_for(duration, [false; 16])
+            );
+            assert_eq!(restored.save_state(), emulator.save_state());
+        }
+    }
+}
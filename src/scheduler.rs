@@ -0,0 +1,318 @@
+use core::time::Duration;
+
+use crate::timing::DISPLAY_CYCLES_PER_FRAME;
+#[cfg(feature = "alloc")]
+use crate::Engine;
+use crate::{Chip8, Chip8Error, DisplayState, Timing, VIP_CYCLES_PER_FRAME};
+
+/// How often the delay and sound timers count down: 1/60 s, rounded down to whole nanoseconds
+/// so that a frontend that runs the emulator for this long every frame gets exactly one tick per frame
+pub const TIMER_PERIOD: Duration = Duration::from_nanos(16_666_666);
+
+/// The speed [`Chip8::run_for`] executes instructions at, unless set with [`Chip8::with_speed`]
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 1200;
+
+/// 60 timer periods, the time unit instruction credit is counted in
+pub(crate) const SECOND_NANOS: i64 = TIMER_PERIOD.as_nanos() as i64 * 60;
+
+/// Emulated time that [`Chip8::run_for`] has been given but not used up yet.
+/// It carries over between calls, so that neither the timers nor the instructions drift
+/// however the time is split up.
+pub(crate) struct Clock {
+    pub(crate) timing: Timing,
+    instructions_per_second: u32,
+    /// Nanoseconds since the last timer tick
+    pub(crate) since_tick: u64,
+    /// Nanoseconds times instructions or machine cycles per second.
+    /// An instruction executes while there is at least [`SECOND_NANOS`],
+    /// and with VIP timing takes that times its machine cycles, which can leave it negative.
+    pub(crate) credit: i64,
+    /// Set by a draw with the display wait quirk, instructions don't execute until the next tick
+    pub(crate) waiting_for_tick: bool,
+}
+
+impl Clock {
+    /// Lets `nanos` of emulated time pass, at `rate` instructions or machine cycles per second
+    fn advance(&mut self, nanos: u64, rate: u32) {
+        self.since_tick += nanos;
+        self.credit += (nanos * rate as u64) as i64;
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            timing: Timing::default(),
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            since_tick: 0,
+            credit: 0,
+            waiting_for_tick: false,
+        }
+    }
+}
+
+/// What happened during [`Chip8::run_for`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub instructions: u64,
+    pub timer_ticks: u32,
+    /// Updated if any of the instructions changed the display
+    pub display: DisplayState,
+    /// True if `stop` returned true before the time ran out, see [`Chip8::run_until`]
+    pub stopped: bool,
+}
+
+impl Chip8 {
+    /// Executes instructions at `instructions_per_second` in [`Chip8::run_for`], unless using [`Timing::Vip`].
+    /// Panics if it is 0.
+    pub fn with_speed(mut self, instructions_per_second: u32) -> Self {
+        assert!(
+            instructions_per_second > 0,
+            "the speed must be at least 1 instruction per second"
+        );
+        self.clock.instructions_per_second = instructions_per_second;
+        self
+    }
+
+    /// Instructions per second in [`Chip8::run_for`]
+    pub fn speed(&self) -> u32 {
+        self.clock.instructions_per_second
+    }
+
+    /// Runs the program for `duration` of emulated time with the given keys held down,
+    /// executing instructions at the configured speed or [`Timing`] and ticking the timers at 60 Hz in between.
+    ///
+    /// Frontends call this with the real time since the previous call, then render the display if it was updated,
+    /// so the display refreshes at whatever rate the frontend runs at.
+    /// Time left over from one call, e.g. half an instruction, counts towards the next.
+    ///
+    /// With the display wait quirk, the rest of the instructions until the next timer tick are skipped after a draw,
+    /// like the original interpreter waiting for the vertical blank interrupt.
+    pub fn run_for(
+        &mut self,
+        duration: Duration,
+        pressed_keys: [bool; 16],
+    ) -> Result<RunSummary, Chip8Error> {
+        self.run_until(duration, pressed_keys, |_| false)
+    }
+
+    /// Like [`Chip8::run_for`], but calls `stop` before each instruction and returns early if it returns true,
+    /// e.g. at a debugger breakpoint or after a fixed number of instructions.
+    /// The instruction `stop` returned true for hasn't executed, and the emulated time
+    /// up to when it would have is all that's used, the rest of `duration` is dropped rather than carried over.
+    pub fn run_until(
+        &mut self,
+        duration: Duration,
+        pressed_keys: [bool; 16],
+        mut stop: impl FnMut(&Chip8) -> bool,
+    ) -> Result<RunSummary, Chip8Error> {
+        let mut summary = RunSummary {
+            instructions: 0,
+            timer_ticks: 0,
+            display: DisplayState::NotUpdated,
+            stopped: false,
+        };
+        let period = TIMER_PERIOD.as_nanos() as u64;
+        let rate = match self.clock.timing {
+            Timing::Instructions => self.clock.instructions_per_second,
+            // the interpreter only gets the cycles the display leaves it
+            Timing::Vip => (VIP_CYCLES_PER_FRAME - DISPLAY_CYCLES_PER_FRAME) * 60,
+        };
+        // at most u64::MAX nanoseconds, which is centuries
+        let mut remaining = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        while remaining > 0 {
+            // run up to the next tick, so that the instructions before it see the timers before it
+            let mut chunk = remaining.min(period - self.clock.since_tick);
+            remaining -= chunk;
+            let mut stopped = false;
+            // called before each instruction, lets time pass until it can execute
+            // or returns false if it can't before the tick
+            let mut proceed = |chip8: &mut Chip8| {
+                if chip8.clock.waiting_for_tick || chip8.exited {
+                    // idle until the tick
+                    chip8.clock.advance(chunk, rate);
+                    let idle = (chip8.clock.credit / SECOND_NANOS).max(0);
+                    chip8.clock.credit -= idle * SECOND_NANOS;
+                    if chip8.clock.timing == Timing::Vip {
+                        chip8.cycles += idle as u64;
+                    }
+                    return false;
+                }
+                // time only turns into credit once an instruction needs it,
+                // so none is used up past an instruction that `stop` returns true for
+                let missing = (SECOND_NANOS - chip8.clock.credit).max(0) as u64;
+                let wait = missing.div_ceil(rate as u64);
+                if wait > chunk {
+                    chip8.clock.advance(chunk, rate);
+                    chunk = 0;
+                    return false;
+                }
+                chunk -= wait;
+                chip8.clock.advance(wait, rate);
+                stopped = stop(chip8);
+                !stopped
+            };
+            while proceed(self) {
+                let (instructions, result) = self.run_instructions(pressed_keys, &mut proceed);
+                summary.instructions += instructions;
+                if result? == DisplayState::Updated {
+                    summary.display = DisplayState::Updated;
+                    self.clock.waiting_for_tick = self.quirks.display_wait;
+                }
+            }
+            if stopped {
+                summary.stopped = true;
+                return Ok(summary);
+            }
+            if self.clock.since_tick == period {
+                self.clock.since_tick = 0;
+                self.clock.waiting_for_tick = false;
+                self.tick_timers();
+                if self.clock.timing == Timing::Vip {
+                    self.cycles += DISPLAY_CYCLES_PER_FRAME as u64;
+                }
+                summary.timer_ticks += 1;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Executes the instruction at pc, then the ones after it for as long as `proceed` returns true before each,
+    /// stopping after one that updates the display or exits.
+    /// Returns how many executed and the result of the last.
+    fn run_instructions(
+        &mut self,
+        pressed_keys: [bool; 16],
+        mut proceed: impl FnMut(&mut Chip8) -> bool,
+    ) -> (u64, Result<DisplayState, Chip8Error>) {
+        #[cfg(feature = "alloc")]
+        if self.engine == Engine::Recompiler {
+            return self.run_blocks(pressed_keys, proceed);
+        }
+        let mut executed = 0;
+        loop {
+            let cycles = self.cycles;
+            let result = self.step(pressed_keys);
+            if result.is_err() {
+                return (executed, result);
+            }
+            executed += 1;
+            self.charge(cycles);
+            if result != Ok(DisplayState::NotUpdated) || self.exited || !proceed(self) {
+                return (executed, result);
+            }
+        }
+    }
+
+    /// Takes the instruction that just executed, which started at `cycles_before` machine cycles, out of the credit
+    pub(crate) fn charge(&mut self, cycles_before: u64) {
+        let cost = match self.clock.timing {
+            Timing::Instructions => 1,
+            Timing::Vip => (self.cycles - cycles_before) as i64,
+        };
+        self.clock.credit -= cost * SECOND_NANOS;
+    }
+}
+
+#[test]
+fn test_run_for() {
+    use crate::Quirks;
+
+    // v0 := 120, delay := v0, loop: v1 := delay, v2 += 1, jump loop
+    let program = [0x60, 120, 0xF0, 0x15, 0xF1, 0x07, 0x72, 0x01, 0x12, 0x04];
+    let mut emulator = Chip8::load_program(&program, Quirks::default())
+        .unwrap()
+        .with_speed(900);
+    // uneven slices of a second of emulated time add up to 60 ticks and the configured speed
+    let mut summary_total = (0, 0);
+    for nanos in [1, 333_333_333, 7, 100_000_000, 566_666_619] {
+        let summary = emulator
+            .run_for(Duration::from_nanos(nanos), [false; 16])
+            .unwrap();
+        summary_total.0 += summary.instructions;
+        summary_total.1 += summary.timer_ticks;
+    }
+    assert_eq!(summary_total, (900, 60));
+    assert_eq!(emulator.timers().delay, 60);
+    assert_eq!(emulator.instruction_count(), 900);
+
+    // a frame is 15 instructions, then a tick
+    let summary = emulator.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+    assert_eq!((summary.instructions, summary.timer_ticks), (15, 1));
+    assert_eq!(emulator.timers().delay, 59);
+
+    let summary = emulator
+        .run_until(TIMER_PERIOD, [false; 16], |chip8| {
+            chip8.instruction_count() == 920
+        })
+        .unwrap();
+    assert!(summary.stopped);
+    assert_eq!(summary.instructions, 5);
+    // the rest of the frame wasn't used, so a frame's time later is as far into the next frame as the stop was:
+    // the stopped instruction, whose time had come, the 9 left before the tick and 6 after it
+    let summary = emulator.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+    assert_eq!((summary.instructions, summary.timer_ticks), (16, 1));
+    assert_eq!(emulator.timers().delay, 58);
+}
+
+#[test]
+fn test_run_for_display_wait() {
+    use crate::Quirks;
+
+    // loop: draw, v0 += 1, jump loop
+    let program = [0xD0, 0x01, 0x70, 0x01, 0x12, 0x00];
+    // with the quirk each frame ends at the draw, so it continues from the add
+    for (display_wait, instructions) in [(false, [20, 20, 20]), (true, [1, 3, 3])] {
+        let quirks = Quirks {
+            display_wait,
+            ..Quirks::default()
+        };
+        let mut emulator = Chip8::load_program(&program, quirks).unwrap();
+        for expected in instructions {
+            let summary = emulator.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+            assert_eq!(summary.instructions, expected);
+            assert_eq!(summary.display, DisplayState::Updated);
+        }
+    }
+}
+
+#[test]
+fn test_run_for_vip_timing() {
+    use crate::{Quirks, VIP_CYCLES_PER_SECOND};
+
+    // loop: v0 += 1, jump loop
+    let program = [0x70, 0x01, 0x12, 0x00];
+    let mut emulator = Chip8::load_program(&program, Quirks::vip())
+        .unwrap()
+        .with_timing(Timing::Vip);
+    let summary = emulator
+        .run_for(Duration::from_secs(1), [false; 16])
+        .unwrap();
+    // the add takes 30 cycles and the jump 32, in what the display leaves of each frame
+    let add_and_jump = 30 + 32;
+    let program_cycles = (VIP_CYCLES_PER_FRAME - DISPLAY_CYCLES_PER_FRAME) * 60;
+    assert_eq!(
+        summary.instructions,
+        2 * (program_cycles / add_and_jump + 1) as u64
+    );
+    // the last instruction can go over by less than its cost
+    assert!(
+        (VIP_CYCLES_PER_SECOND as u64..VIP_CYCLES_PER_SECOND as u64 + 32)
+            .contains(&emulator.cycles())
+    );
+
+    // loop: draw, v0 += 1, jump loop
+    let program = [0xD0, 0x01, 0x70, 0x01, 0x12, 0x00];
+    let mut emulator = Chip8::load_program(&program, Quirks::vip())
+        .unwrap()
+        .with_timing(Timing::Vip);
+    // with the display wait quirk the draw stalls until the interrupt, so each frame takes exactly its cycles
+    for (frame, expected) in [1, 3, 3].into_iter().enumerate() {
+        let summary = emulator.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+        assert_eq!(summary.instructions, expected);
+        assert_eq!(
+            emulator.cycles(),
+            (frame as u64 + 1) * VIP_CYCLES_PER_FRAME as u64
+        );
+    }
+}
@@ -0,0 +1,157 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::Chip8;
+
+/// How many return addresses fit on the stack without the `alloc` feature
+pub const FIXED_STACK_DEPTH: usize = 16;
+
+/// How many return addresses the stack holds before 2NNN fails with [`crate::Chip8Error::StackOverflow`].
+/// Without the `alloc` feature it's never more than [`FIXED_STACK_DEPTH`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackDepth {
+    Limited(usize),
+    /// Grows until memory runs out, which a program that recurses forever will eventually do
+    Unlimited,
+}
+
+impl StackDepth {
+    /// The original interpreter on the COSMAC VIP has room for 12 return addresses
+    pub const VIP: StackDepth = StackDepth::Limited(12);
+    /// SUPER-CHIP has room for 16
+    pub const SCHIP: StackDepth = StackDepth::Limited(16);
+}
+
+impl Default for StackDepth {
+    fn default() -> Self {
+        StackDepth::SCHIP
+    }
+}
+
+/// Return addresses of the subroutines being executed, the most recent call last.
+/// Grows as needed up to its depth with the `alloc` feature, otherwise it holds up to [`FIXED_STACK_DEPTH`] addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Stack {
+    #[cfg(feature = "alloc")]
+    entries: Vec<u16>,
+    #[cfg(not(feature = "alloc"))]
+    entries: [u16; FIXED_STACK_DEPTH],
+    #[cfg(not(feature = "alloc"))]
+    len: usize,
+    pub(crate) depth: StackDepth,
+}
+
+impl Stack {
+    /// An empty stack, for restoring a saved state into
+    #[cfg(feature = "alloc")]
+    pub(crate) fn new(depth: StackDepth) -> Self {
+        Stack {
+            depth,
+            ..Stack::default()
+        }
+    }
+
+    /// Whether a push would fail
+    pub(crate) fn is_full(&self) -> bool {
+        let limit = match self.depth {
+            StackDepth::Limited(limit) => limit,
+            StackDepth::Unlimited => usize::MAX,
+        };
+        self.as_slice().len() >= limit
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Stack {
+    /// Returns false if the stack is full
+    #[must_use]
+    pub(crate) fn push(&mut self, addr: u16) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.entries.push(addr);
+        true
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u16> {
+        self.entries.pop()
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u16] {
+        &self.entries
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl Stack {
+    /// Returns false if the stack is full
+    #[must_use]
+    pub(crate) fn push(&mut self, addr: u16) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let Some(entry) = self.entries.get_mut(self.len) else {
+            return false;
+        };
+        *entry = addr;
+        self.len += 1;
+        true
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u16> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.entries[self.len])
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u16] {
+        &self.entries[..self.len]
+    }
+}
+
+impl Chip8 {
+    /// Limits the stack to `depth` return addresses. Addresses already on the stack stay there,
+    /// but nothing more can be pushed until there are fewer than `depth`.
+    pub fn with_stack_depth(mut self, depth: StackDepth) -> Self {
+        self.stack.depth = depth;
+        self
+    }
+
+    pub fn stack_depth(&self) -> StackDepth {
+        self.stack.depth
+    }
+}
+
+#[test]
+fn test_stack_depth() {
+    use crate::{Chip8Error, Quirks};
+
+    // call 0x200
+    let program = [0x22, 0x00];
+    for (depth, calls) in [(StackDepth::VIP, 12), (StackDepth::SCHIP, 16)] {
+        let mut emulator = Chip8::load_program(&program, Quirks::default())
+            .unwrap()
+            .with_stack_depth(depth);
+        for _ in 0..calls {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.stack().len(), calls);
+        assert_eq!(
+            emulator.step([false; 16]),
+            Err(Chip8Error::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    {
+        let mut emulator = Chip8::load_program(&program, Quirks::default())
+            .unwrap()
+            .with_stack_depth(StackDepth::Unlimited);
+        for _ in 0..1000 {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.stack().len(), 1000);
+    }
+}
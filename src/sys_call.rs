@@ -0,0 +1,104 @@
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+use crate::{Chip8, Chip8Error, DisplayState};
+
+/// Emulates the machine code routines that hybrid programs call with 0NNN,
+/// e.g. with native versions of the few 1802 instructions they run on the COSMAC VIP.
+///
+/// Closures that take the emulator and the routine's address implement it too.
+pub trait SysCallHandler {
+    /// Runs the routine at `addr`. The emulator's pc is already past the 0NNN instruction,
+    /// and whatever the handler changes stays changed when the program continues.
+    /// An error stops the program like an instruction that fails.
+    fn sys_call(&mut self, chip8: &mut Chip8, addr: u16) -> Result<DisplayState, Chip8Error>;
+}
+
+impl<F> SysCallHandler for F
+where
+    F: FnMut(&mut Chip8, u16) -> Result<DisplayState, Chip8Error>,
+{
+    fn sys_call(&mut self, chip8: &mut Chip8, addr: u16) -> Result<DisplayState, Chip8Error> {
+        self(chip8, addr)
+    }
+}
+
+/// What 0NNN does, other than 0000 which is always invalid
+#[derive(Default)]
+pub enum SysCallPolicy {
+    /// Continue with the next instruction, like most interpreters since the COSMAC VIP
+    Ignore,
+    /// Stop with [`Chip8Error::MachineCall`]
+    #[default]
+    Trap,
+    /// Call the handler
+    #[cfg(feature = "alloc")]
+    Handler(Box<dyn SysCallHandler>),
+}
+
+impl Chip8 {
+    /// Handles 0NNN according to `policy`
+    pub fn with_sys_call_policy(mut self, policy: SysCallPolicy) -> Self {
+        self.sys_calls = policy;
+        self
+    }
+
+    /// Handles 0NNN by calling `handler`, the same as [`SysCallPolicy::Handler`]
+    #[cfg(feature = "alloc")]
+    pub fn with_sys_call_handler(self, handler: impl SysCallHandler + 'static) -> Self {
+        self.with_sys_call_policy(SysCallPolicy::Handler(Box::new(handler)))
+    }
+
+    /// Executes the 0NNN at `pc`, with pc already past it
+    pub(crate) fn sys_call(&mut self, pc: u16, opcode: u16) -> Result<DisplayState, Chip8Error> {
+        match &mut self.sys_calls {
+            SysCallPolicy::Ignore => Ok(DisplayState::NotUpdated),
+            SysCallPolicy::Trap => Err(Chip8Error::MachineCall { pc, opcode }),
+            #[cfg(feature = "alloc")]
+            SysCallPolicy::Handler(_) => {
+                // the handler gets the emulator to itself while it runs
+                let policy = core::mem::take(&mut self.sys_calls);
+                let SysCallPolicy::Handler(mut handler) = policy else {
+                    unreachable!("the policy was just matched")
+                };
+                let result = handler.sys_call(self, opcode & 0x0FFF);
+                self.sys_calls = SysCallPolicy::Handler(handler);
+                result
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sys_call_policy() {
+    use crate::Quirks;
+
+    // 0x300, v0 := 1
+    let program = [0x03, 0x00, 0x60, 0x01];
+    let mut emulator = Chip8::load_program(&program, Quirks::vip()).unwrap();
+    assert_eq!(
+        emulator.step([false; 16]),
+        Err(Chip8Error::MachineCall {
+            pc: 0x200,
+            opcode: 0x0300
+        })
+    );
+
+    let mut emulator = Chip8::load_program(&program, Quirks::vip())
+        .unwrap()
+        .with_sys_call_policy(SysCallPolicy::Ignore);
+    emulator.step([false; 16]).unwrap();
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(emulator.registers()[0], 1);
+
+    // a native version of a routine that sets VF from its address
+    let mut emulator = Chip8::load_program(&program, Quirks::vip())
+        .unwrap()
+        .with_sys_call_handler(|chip8: &mut Chip8, addr| {
+            chip8.set_register(0xF, (addr >> 4) as u8);
+            Ok(DisplayState::NotUpdated)
+        });
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(emulator.registers()[0xF], 0x30);
+    assert_eq!(emulator.pc(), 0x202);
+}
@@ -14,15 +14,20 @@ use termion::screen::IntoAlternateScreen;
 use crate::IODevice;
 use crate::UserInput;
 
+use chiprs::{AudioPattern, Display};
+
 const OFF_COLOR_CODE: i32 = 232;
 const ON_COLOR_CODE: i32 = 214;
+/// 256-color codes for each combination of the XO-CHIP bitplanes
+const COLOR_CODES: [i32; 4] = [OFF_COLOR_CODE, ON_COLOR_CODE, 202, 94];
 
 pub struct TerminalWindow {
     /// The display state is None when uninitialized, before the first display state is received from the emulator
-    prev_display_state: Option<[[bool; 64]; 32]>,
+    prev_display_state: Option<Display>,
     stdout: termion::screen::AlternateScreen<termion::raw::RawTerminal<Stdout>>,
     stdin: termion::AsyncReader,
     last_key_press_times: [Option<time::Instant>; 16],
+    last_rewind_press_time: Option<time::Instant>,
 }
 
 impl TerminalWindow {
@@ -39,6 +44,7 @@ impl TerminalWindow {
             stdout,
             stdin: termion::async_stdin(),
             last_key_press_times: [None; 16],
+            last_rewind_press_time: None,
         }
     }
 }
@@ -67,6 +73,10 @@ impl IODevice for TerminalWindow {
                     'v' => self.last_key_press_times[0xF] = Some(Instant::now()),
                     _ => {}
                 },
+                // F1-F4 save to slots 1-4, F5-F8 load from slots 1-4
+                Ok(Key::Backspace) => self.last_rewind_press_time = Some(Instant::now()),
+                Ok(Key::F(n @ 1..=4)) => return UserInput::SaveState(n),
+                Ok(Key::F(n @ 5..=8)) => return UserInput::LoadState(n - 4),
                 Ok(Key::Ctrl('c')) => {
                     // Show the cursor
                     return UserInput::Exit;
@@ -76,6 +86,12 @@ impl IODevice for TerminalWindow {
         }
 
         let now = Instant::now();
+        // the terminal only reports key presses, so a key counts as held while it keeps repeating
+        if let Some(t) = self.last_rewind_press_time {
+            if now - t < Duration::from_millis(50) {
+                return UserInput::Rewind;
+            }
+        }
         let pressed_keys = self.last_key_press_times.map(|t| match t {
             Some(t) => now - t < Duration::from_millis(50),
             None => false,
@@ -83,12 +99,12 @@ impl IODevice for TerminalWindow {
         UserInput::PressedKeys(pressed_keys)
     }
 
-    fn render(&mut self, display: &[[bool; 64]; 32]) -> Result<(), Box<dyn std::error::Error>> {
+    fn render(&mut self, display: &Display) -> Result<(), Box<dyn std::error::Error>> {
         if self.prev_display_state == Some(*display) {
             return Ok(());
         }
 
-        let display_string = generate_display_string(*display);
+        let display_string = generate_display_string(display);
         write!(self.stdout, "{display_string}").unwrap();
         self.stdout.flush().unwrap();
         self.prev_display_state = Some(*display);
@@ -97,7 +113,7 @@ impl IODevice for TerminalWindow {
 
     fn pause_beep(&mut self) {}
 
-    fn resume_beep(&mut self) {}
+    fn resume_beep(&mut self, _pattern: Option<AudioPattern>) {}
 }
 
 impl Drop for TerminalWindow {
@@ -109,7 +125,8 @@ impl Drop for TerminalWindow {
 }
 
 // Generate a string, that when printed in raw mode, draws the display to the terminal window
-fn generate_display_string(display: [[bool; 64]; 32]) -> String {
+// Each character covers two rows of pixels, so the high resolution mode takes 128x32 characters.
+fn generate_display_string(display: &Display) -> String {
     let mut output = String::new();
     // Hide the cursor before rendering
     output.push_str("\x1b[?25l");
@@ -120,27 +137,42 @@ fn generate_display_string(display: [[bool; 64]; 32]) -> String {
     let upper_half_block = '▀';
     let full_block = '█';
     assert!(
-        display.len() % 2 == 0,
+        display.height().is_multiple_of(2),
         "Expected an even number of rows in the display, got {}",
-        display.len()
+        display.height()
     );
     // set the background color
     output.push_str(format!("\x1b[48;5;{}m", OFF_COLOR_CODE).as_str());
     // set the foreground color
     output.push_str(format!("\x1b[38;5;{}m", ON_COLOR_CODE).as_str());
-    for row_idx in (0..display.len()).step_by(2) {
-        for col_idx in 0..display[0].len() {
-            let top_pixel = display[row_idx][col_idx];
-            let bottom_pixel = display[row_idx + 1][col_idx];
-            if top_pixel && bottom_pixel {
-                output.push(full_block)
-            } else if top_pixel {
-                output.push(upper_half_block);
-            } else if bottom_pixel {
-                output.push(lower_half_block);
+    let (mut background, mut foreground) = (0, 1);
+    let mut rows = display.rows();
+    while let (Some(top_row), Some(bottom_row)) = (rows.next(), rows.next()) {
+        for (&top_pixel, &bottom_pixel) in top_row.iter().zip(bottom_row) {
+            // pick a character and the colors to draw it with
+            let (block, fg, bg) = if top_pixel == bottom_pixel {
+                if top_pixel == 0 {
+                    (' ', foreground, 0)
+                } else {
+                    (full_block, top_pixel, background)
+                }
+            } else if bottom_pixel == 0 {
+                (upper_half_block, top_pixel, 0)
+            } else if top_pixel == 0 {
+                (lower_half_block, bottom_pixel, 0)
             } else {
-                output.push(' ');
+                (upper_half_block, top_pixel, bottom_pixel)
+            };
+            // only colored XO-CHIP pixels need to change the colors set at the start
+            if fg != foreground {
+                output.push_str(format!("\x1b[38;5;{}m", COLOR_CODES[fg as usize]).as_str());
+                foreground = fg;
+            }
+            if bg != background {
+                output.push_str(format!("\x1b[48;5;{}m", COLOR_CODES[bg as usize]).as_str());
+                background = bg;
             }
+            output.push(block);
         }
         // Need to push a carriage return because \n does not set the cursor position to the beginning of the line in raw mode.
         output.push_str("\r\n");
@@ -150,21 +182,24 @@ fn generate_display_string(display: [[bool; 64]; 32]) -> String {
 
 #[test]
 fn test_generate_display_string() {
-    let mut display = [[false; 64]; 32];
-    for row_idx in 0..32 {
-        for col_idx in 0..64 {
-            display[row_idx][col_idx] = match row_idx % 2 == 0 {
-                false => match col_idx % 4 {
-                    0 | 3 => true,
-                    _ => false,
-                },
-                true => match col_idx % 4 {
-                    1 | 3 => true,
-                    _ => false,
-                },
-            }
+    let mut display = Display::default();
+    for row_idx in 0..display.height() {
+        for col_idx in 0..display.width() {
+            let pixel = match row_idx % 2 == 0 {
+                false => matches!(col_idx % 4, 0 | 3),
+                true => matches!(col_idx % 4, 1 | 3),
+            };
+            display.set(col_idx, row_idx, pixel as u8);
         }
     }
-    let display_str = generate_display_string(display);
-    print!("{display_str}");
+    let display_str = generate_display_string(&display);
+    // each character covers a column of two rows, with only the default colors in use
+    let line = "▄▀ █".repeat(16) + "\r\n";
+    assert_eq!(
+        display_str,
+        format!(
+            "\x1b[?25l\x1b[H\x1b[48;5;{OFF_COLOR_CODE}m\x1b[38;5;{ON_COLOR_CODE}m{}",
+            line.repeat(16)
+        )
+    );
 }
@@ -0,0 +1,125 @@
+use crate::disasm::Instruction;
+use crate::Chip8;
+
+/// The VIP's RCA 1802 runs at 1.76064 MHz and takes 8 clock cycles per machine cycle
+pub const VIP_CYCLES_PER_SECOND: u32 = 220_080;
+
+/// Machine cycles in each 60 Hz frame
+pub const VIP_CYCLES_PER_FRAME: u32 = VIP_CYCLES_PER_SECOND / 60;
+
+/// The part of each frame the interpreter doesn't get: the CDP1861 takes a cycle for each of the
+/// 8 bytes of its 128 lines, and the interrupt routine that sets it up and counts down the timers takes about 60 more
+pub(crate) const DISPLAY_CYCLES_PER_FRAME: u32 = 128 * 8 + 60;
+
+/// Fetching an instruction and jumping to the routine for it
+const FETCH_CYCLES: u32 = 20;
+
+/// How [`Chip8::run_for`] decides when instructions execute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// Every instruction takes the same time, at the speed set by [`Chip8::with_speed`]
+    #[default]
+    Instructions,
+    /// Each instruction takes as many machine cycles as it would in the original interpreter on the COSMAC VIP,
+    /// which for DXYN depends on the height and alignment of the sprite.
+    /// The costs are approximate, but close enough for programs that depend on the original speed.
+    Vip,
+}
+
+impl Chip8 {
+    /// Executes instructions with `timing` in [`Chip8::run_for`]
+    pub fn with_timing(mut self, timing: Timing) -> Self {
+        self.clock.timing = timing;
+        self
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.clock.timing
+    }
+
+    /// COSMAC VIP machine cycles that have elapsed with [`Timing::Vip`],
+    /// including the time taken by the display and spent waiting for it.
+    /// Always 0 with [`Timing::Instructions`].
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The machine cycles the VIP interpreter would take to execute the instruction at pc, given the current registers
+    pub(crate) fn vip_cycles(&self) -> u32 {
+        use Instruction::*;
+        let v = |x: u8| self.registers[x as usize];
+        // skips take a little longer when they skip
+        let skip = |taken: bool| if taken { 14 } else { 10 };
+        let execute = match Instruction::decode(self.read_u16(self.pc), 0) {
+            Clear => 24,
+            Return => 10,
+            Jump(_) => 12,
+            Call(_) => 26,
+            // the routine itself is part of the program, this is just the call
+            MachineCall(_) => 26,
+            SkipIfEqual { x, value } => skip(v(x) == value),
+            SkipIfNotEqual { x, value } => skip(v(x) != value),
+            SkipIfRegistersEqual { x, y } => skip(v(x) == v(y)) + 4,
+            SkipIfRegistersNotEqual { x, y } => skip(v(x) != v(y)) + 4,
+            Set { .. } => 6,
+            Add { .. } => 10,
+            // the 8XYN instructions run a generated two-instruction subroutine
+            Copy { .. }
+            | Or { .. }
+            | And { .. }
+            | Xor { .. }
+            | AddRegister { .. }
+            | Sub { .. }
+            | ShiftRight { .. }
+            | SubReversed { .. }
+            | ShiftLeft { .. } => 44,
+            SetIndex(_) => 12,
+            JumpOffset(_) => 22,
+            Random { .. } => 36,
+            Draw { x, height, .. } => draw_cycles(v(x), height),
+            SkipIfKey(_) | SkipIfNotKey(_) => 18,
+            GetDelay(_) | SetDelay(_) | SetSound(_) => 10,
+            WaitKey(_) => 10,
+            AddIndex(_) => 16,
+            SmallFont(_) => 20,
+            // each digit is found by repeated subtraction
+            Bcd(x) => {
+                let value = v(x) as u32;
+                24 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+            }
+            Store(x) | Load(x) => 14 + 14 * (x as u32 + 1),
+            // the instructions the VIP interpreter doesn't have
+            _ => 10,
+        };
+        FETCH_CYCLES + execute
+    }
+}
+
+/// Drawing XORs each row of the sprite into one byte of display memory,
+/// or into two after shifting it one bit at a time when X isn't a multiple of 8
+fn draw_cycles(x: u8, height: u8) -> u32 {
+    let (rows, bytes_per_row) = if height == 0 {
+        (16, 2)
+    } else {
+        (height as u32, 1)
+    };
+    let shift = (x % 8) as u32;
+    let row_cycles = if shift == 0 { 20 } else { 30 + 4 * shift };
+    26 + rows * bytes_per_row * row_cycles
+}
+
+#[test]
+fn test_vip_cycles() {
+    use crate::Quirks;
+
+    // v0 := 3, draw at v0 v0, v1 := 199, bcd v1
+    let program = [0x60, 0x03, 0xD0, 0x05, 0x61, 199, 0xF1, 0x33];
+    let mut emulator = Chip8::load_program(&program, Quirks::vip()).unwrap();
+    emulator.step([false; 16]).unwrap();
+    // an unaligned sprite costs more than an aligned one
+    assert_eq!(emulator.vip_cycles(), FETCH_CYCLES + draw_cycles(3, 5));
+    assert!(draw_cycles(3, 5) > draw_cycles(8, 5));
+    emulator.step([false; 16]).unwrap();
+    emulator.step([false; 16]).unwrap();
+    assert_eq!(emulator.vip_cycles(), FETCH_CYCLES + 24 + 16 * 19);
+}
@@ -0,0 +1,135 @@
+#[cfg(feature = "alloc")]
+use alloc::string::{String, ToString};
+#[cfg(feature = "alloc")]
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::fmt::Write;
+
+use crate::disasm::Instruction;
+
+/// The machine state just before an instruction executes, passed to the hook set with
+/// [`Chip8::with_trace_hook`](crate::Chip8::with_trace_hook).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// number of return addresses on the stack
+    pub stack_depth: usize,
+}
+
+/// Layouts for the line [`TraceEntry::to_line`] writes for an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// The [`Display`](core::fmt::Display) format of [`TraceEntry`]
+    #[default]
+    Chiprs,
+    /// The state lines [Cadmium](https://github.com/gulrak/cadmium) writes when tracing, e.g.
+    ///
+    /// `V0:00 V1:00 ... VF:00 I:0000 SP:0 PC:0200 O:6a05`
+    ///
+    /// with lowercase hex and the stack depth as SP, so the two emulators' traces of a program
+    /// can be diffed line by line.
+    Cadmium,
+}
+
+#[cfg(feature = "alloc")]
+impl TraceEntry {
+    pub fn to_line(&self, format: TraceFormat) -> String {
+        match format {
+            TraceFormat::Chiprs => self.to_string(),
+            TraceFormat::Cadmium => {
+                let mut line = String::new();
+                for (idx, value) in self.registers.iter().enumerate() {
+                    // writing to a String can't fail
+                    let _ = write!(line, "V{idx:X}:{value:02x} ");
+                }
+                let _ = write!(
+                    line,
+                    "I:{:04x} SP:{:x} PC:{:04x} O:{:04x}",
+                    self.index, self.stack_depth, self.pc, self.opcode
+                );
+                line
+            }
+        }
+    }
+}
+
+/// One line per instruction, e.g.
+///
+/// `PC:0200 OP:6A05 V0:00 V1:00 ... VF:00 I:0000 DT:00 ST:00 SP:0 | LD VA, 0x05`
+///
+/// Every field is a `NAME:HEX` pair separated by spaces, in a fixed order and width, and the
+/// mnemonic comes last after a `|`. Fields can be cut out to diff against traces from other
+/// emulators that record a different selection of them.
+#[cfg(feature = "alloc")]
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PC:{:04X} OP:{:04X}", self.pc, self.opcode)?;
+        for (idx, value) in self.registers.iter().enumerate() {
+            write!(f, " V{idx:X}:{value:02X}")?;
+        }
+        write!(
+            f,
+            " I:{:04X} DT:{:02X} ST:{:02X} SP:{:X} | {}",
+            self.index, self.delay_timer, self.sound_timer, self.stack_depth, self.instruction
+        )
+    }
+}
+
+#[test]
+fn test_trace_hook() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::{Chip8, Quirks};
+
+    // va := 5, call 0x206, (0x204) jump 0x204, (0x206) i := 0x300
+    let program = [0x6A, 0x05, 0x22, 0x06, 0x12, 0x04, 0xA3, 0x00];
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let hook_lines = lines.clone();
+    let mut emulator = Chip8::load_program(&program, Quirks::default())
+        .unwrap()
+        .with_trace_hook(move |entry| hook_lines.borrow_mut().push(entry.to_string()));
+    for _ in 0..3 {
+        emulator.step([false; 16]).unwrap();
+    }
+    let zeros = " V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00";
+    assert_eq!(
+        *lines.borrow(),
+        [
+            format!("PC:0200 OP:6A05{zeros} VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 DT:00 ST:00 SP:0 | LD VA, 0x05"),
+            format!("PC:0202 OP:2206{zeros} VA:05 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 DT:00 ST:00 SP:0 | CALL 0x206"),
+            format!("PC:0206 OP:A300{zeros} VA:05 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 DT:00 ST:00 SP:1 | LD I, 0x300"),
+        ]
+    );
+}
+
+#[test]
+fn test_cadmium_format() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::{Chip8, Quirks};
+
+    // va := 5, call 0x206, (0x206) i := 0x3ab
+    let program = [0x6A, 0x05, 0x22, 0x06, 0x00, 0x00, 0xA3, 0xAB];
+    let last_line = Rc::new(RefCell::new(String::new()));
+    let hook_line = last_line.clone();
+    let mut emulator = Chip8::load_program(&program, Quirks::default())
+        .unwrap()
+        .with_trace_hook(move |entry| {
+            *hook_line.borrow_mut() = entry.to_line(TraceFormat::Cadmium)
+        });
+    for _ in 0..3 {
+        emulator.step([false; 16]).unwrap();
+    }
+    assert_eq!(
+        *last_line.borrow(),
+        "V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00 VA:05 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 SP:1 PC:0206 O:a3ab"
+    );
+}
@@ -0,0 +1,377 @@
+use core::time::Duration;
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::scheduler::SECOND_NANOS;
+use crate::{
+    AudioPattern, Chip8Error, Display, DisplayState, Machine, RunSummary, LORES_WIDTH,
+    PROGRAM_START, TIMER_PERIOD, VIP_CYCLES_PER_FRAME, VIP_CYCLES_PER_SECOND,
+};
+
+/// RAM of a COSMAC VIP with the 4K expansion, repeated up to 0x7FFF
+pub const VIP_RAM_SIZE: usize = 0x1000;
+
+/// The monitor ROM, repeated from 0x8000 to 0xFFFF
+pub const VIP_ROM_SIZE: usize = 0x200;
+
+/// The CDP1861 takes 14 machine cycles to scan a line, and 262 lines make a frame
+const CYCLES_PER_LINE: u32 = 14;
+
+/// The lines the CDP1861 fetches from memory with DMA, 8 bytes each
+const FIRST_DISPLAY_LINE: u32 = 80;
+const DISPLAY_LINES: usize = 128;
+const END_DISPLAY_LINE: u32 = FIRST_DISPLAY_LINE + DISPLAY_LINES as u32;
+
+/// The CDP1861 requests an interrupt during the two lines before the display,
+/// 29 machine cycles before the first DMA
+const INTERRUPT_LINE: u32 = FIRST_DISPLAY_LINE - 2;
+const DMA_OFFSET: u32 = 1;
+
+/// EF1 is asserted for the 4 lines before the display starts and before it ends,
+/// so that the interrupt routine can wait for them
+const EF1_LINES: u32 = 4;
+
+/// What to put in memory to run a program on the original interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VipImage<'a> {
+    /// The monitor ROM, at most [`VIP_ROM_SIZE`] bytes.
+    /// The CHIP-8 interpreter uses its display interrupt routine and keypad subroutines, so it's always needed.
+    pub rom: &'a [u8],
+    /// The CHIP-8 interpreter, loaded at 0, which the monitor runs when C isn't held down at reset
+    pub interpreter: &'a [u8],
+    /// The CHIP-8 program, loaded at 0x200
+    pub program: &'a [u8],
+}
+
+/// A COSMAC VIP: an RCA 1802 CPU, a CDP1861 video chip, the hex keypad and a speaker driven by Q.
+///
+/// Rather than emulating the CHIP-8 instructions, it runs the original interpreter from an image,
+/// so programs behave exactly as they did on the VIP, including hybrid programs that call
+/// their own 1802 machine code with 0NNN.
+pub struct Vip {
+    cpu: Cdp1802,
+    bus: VipBus,
+    /// The picture the CDP1861 showed in the last frame.
+    /// Every other line is shown with each pixel doubled horizontally, so that it keeps its shape
+    /// in 128x64 pixels, and the 4 lines of each CHIP-8 row become 2.
+    pub display: Display,
+    /// 1802 instructions executed
+    instruction_count: u64,
+    /// Nanoseconds times machine cycles per second that [`Vip::run_for`] hasn't used up yet.
+    /// Instructions and DMA take it below 0 when they don't fit.
+    credit: i64,
+}
+
+/// Everything on the CPU's bus
+struct VipBus {
+    ram: [u8; VIP_RAM_SIZE],
+    rom: [u8; VIP_ROM_SIZE],
+    /// Set by a reset so that the CPU starts in the monitor, and cleared by the first address with A15 set
+    rom_at_zero: bool,
+    /// The CDP1861 is turned on by INP 1 and off by OUT 1
+    display_on: bool,
+    /// The key selected with OUT 2, which asserts EF3 while it's held down
+    selected_key: u8,
+    pressed_keys: [bool; 16],
+    /// Machine cycles since the start of the frame
+    frame_cycle: u32,
+    /// The next line the CDP1861 fetches in this frame
+    dma_line: u32,
+    /// The bytes fetched for each line in this frame. Lines before the display was turned on are blank.
+    lines: [[u8; 8]; DISPLAY_LINES],
+}
+
+impl Vip {
+    /// Loads the images into ROM and RAM and resets the VIP, which starts the monitor.
+    ///
+    /// Fails if the ROM is too large, if the interpreter doesn't leave room for the program at 0x200,
+    /// or if the program doesn't fit in RAM.
+    pub fn load(image: &VipImage<'_>) -> Result<Self, Chip8Error> {
+        if image.rom.len() > VIP_ROM_SIZE {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: image.rom.len(),
+                max_size: VIP_ROM_SIZE,
+            });
+        }
+        if image.interpreter.len() > PROGRAM_START as usize {
+            return Err(Chip8Error::ReservedMemory {
+                addr: PROGRAM_START,
+                reserved_end: image.interpreter.len() as u16,
+            });
+        }
+        let start = PROGRAM_START as usize;
+        if image.program.len() > VIP_RAM_SIZE - start {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: image.program.len(),
+                max_size: VIP_RAM_SIZE - start,
+            });
+        }
+        let mut bus = VipBus {
+            ram: [0; VIP_RAM_SIZE],
+            rom: [0; VIP_ROM_SIZE],
+            rom_at_zero: true,
+            display_on: false,
+            selected_key: 0,
+            pressed_keys: [false; 16],
+            frame_cycle: 0,
+            dma_line: FIRST_DISPLAY_LINE,
+            lines: [[0; 8]; DISPLAY_LINES],
+        };
+        bus.rom[..image.rom.len()].copy_from_slice(image.rom);
+        bus.ram[..image.interpreter.len()].copy_from_slice(image.interpreter);
+        bus.ram[start..start + image.program.len()].copy_from_slice(image.program);
+        let mut display = Display::default();
+        display.set_hires(true);
+        Ok(Vip {
+            cpu: Cdp1802::new(),
+            bus,
+            display,
+            instruction_count: 0,
+            credit: 0,
+        })
+    }
+
+    /// The RAM, including the interpreter's variables, stack and display buffer in the last pages
+    pub fn memory(&self) -> &[u8; VIP_RAM_SIZE] {
+        &self.bus.ram
+    }
+
+    /// 1802 instructions executed since the reset
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// The VIP beeps while Q is set, which the interpreter does while the sound timer is non-zero
+    pub fn is_sound_on(&self) -> bool {
+        self.cpu.q
+    }
+
+    /// Runs the VIP for `duration` of emulated time with the given keys held down, see [`crate::Chip8::run_for`].
+    /// Every frame the CDP1861 interrupts the CPU and fetches the display from memory,
+    /// and `display` is updated at the end of it.
+    ///
+    /// The summary counts 1802 instructions, and frames as timer ticks.
+    /// It never fails: unlike [`crate::Chip8`], the VIP has no instructions it can't execute.
+    pub fn run_for(
+        &mut self,
+        duration: Duration,
+        pressed_keys: [bool; 16],
+    ) -> Result<RunSummary, Chip8Error> {
+        let mut summary = RunSummary {
+            instructions: 0,
+            timer_ticks: 0,
+            display: DisplayState::NotUpdated,
+            stopped: false,
+        };
+        self.bus.pressed_keys = pressed_keys;
+        let period = TIMER_PERIOD.as_nanos() as u64;
+        // at most u64::MAX nanoseconds, which is centuries
+        let mut remaining = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        while remaining > 0 {
+            // a frame at a time, so that the credit can't overflow
+            let chunk = remaining.min(period);
+            remaining -= chunk;
+            self.credit += (chunk * VIP_CYCLES_PER_SECOND as u64) as i64;
+            while self.credit >= SECOND_NANOS {
+                let cycles = self.cycle(&mut summary);
+                self.credit -= cycles as i64 * SECOND_NANOS;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Services a DMA or interrupt request from the CDP1861 if there is one,
+    /// otherwise executes an instruction, and returns the machine cycles it took
+    fn cycle(&mut self, summary: &mut RunSummary) -> u32 {
+        let bus = &mut self.bus;
+        // lines that passed while the display was off aren't fetched
+        let line = bus.frame_cycle / CYCLES_PER_LINE;
+        bus.dma_line = bus.dma_line.max(line);
+        let cycles = if bus.display_on
+            && bus.dma_line < END_DISPLAY_LINE
+            && bus.frame_cycle >= bus.dma_line * CYCLES_PER_LINE + DMA_OFFSET
+        {
+            let row = (bus.dma_line - FIRST_DISPLAY_LINE) as usize;
+            let mut bytes = [0; 8];
+            for byte in &mut bytes {
+                *byte = self.cpu.dma_out(bus);
+            }
+            bus.lines[row] = bytes;
+            bus.dma_line += 1;
+            8
+        } else if (bus.display_on
+            && (INTERRUPT_LINE..FIRST_DISPLAY_LINE).contains(&line)
+            && self.cpu.interrupt())
+            || self.cpu.idle
+        {
+            // responding to the interrupt takes a cycle, and so does each cycle of waiting in IDL
+            1
+        } else {
+            self.instruction_count += 1;
+            summary.instructions += 1;
+            self.cpu.step(bus)
+        };
+        bus.frame_cycle += cycles;
+        if bus.frame_cycle >= VIP_CYCLES_PER_FRAME {
+            bus.frame_cycle -= VIP_CYCLES_PER_FRAME;
+            if self.end_frame() == DisplayState::Updated {
+                summary.display = DisplayState::Updated;
+            }
+            summary.timer_ticks += 1;
+        }
+        cycles
+    }
+
+    /// Shows the lines fetched in the frame that just ended, and starts the next one
+    fn end_frame(&mut self) -> DisplayState {
+        let mut display = Display::default();
+        display.set_hires(true);
+        for (y, line) in self.bus.lines.iter().step_by(2).enumerate() {
+            for x in 0..LORES_WIDTH {
+                let pixel = line[x / 8] >> (7 - x % 8) & 1;
+                display.set(2 * x, y, pixel);
+                display.set(2 * x + 1, y, pixel);
+            }
+        }
+        self.bus.lines = [[0; 8]; DISPLAY_LINES];
+        self.bus.dma_line = FIRST_DISPLAY_LINE;
+        if display == self.display {
+            return DisplayState::NotUpdated;
+        }
+        self.display = display;
+        DisplayState::Updated
+    }
+}
+
+impl Machine for Vip {
+    fn run_for(
+        &mut self,
+        duration: Duration,
+        pressed_keys: [bool; 16],
+    ) -> Result<RunSummary, Chip8Error> {
+        Vip::run_for(self, duration, pressed_keys)
+    }
+
+    fn display(&self) -> &Display {
+        &self.display
+    }
+
+    fn is_sound_on(&self) -> bool {
+        Vip::is_sound_on(self)
+    }
+
+    /// The VIP only has a plain beep
+    fn audio_pattern(&self) -> Option<AudioPattern> {
+        None
+    }
+
+    /// The VIP runs until it's switched off
+    fn has_exited(&self) -> bool {
+        false
+    }
+
+    fn instruction_count(&self) -> u64 {
+        Vip::instruction_count(self)
+    }
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr & 0x8000 != 0 {
+            self.rom_at_zero = false;
+        }
+        if addr & 0x8000 != 0 || self.rom_at_zero {
+            self.rom[addr as usize % VIP_ROM_SIZE]
+        } else {
+            self.ram[addr as usize % VIP_RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr & 0x8000 != 0 {
+            self.rom_at_zero = false;
+        } else {
+            self.ram[addr as usize % VIP_RAM_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.selected_key = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        // nothing drives the data bus
+        0xFF
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        let line = self.frame_cycle / CYCLES_PER_LINE;
+        match flag {
+            1 => {
+                (FIRST_DISPLAY_LINE - EF1_LINES..FIRST_DISPLAY_LINE).contains(&line)
+                    || (END_DISPLAY_LINE - EF1_LINES..END_DISPLAY_LINE).contains(&line)
+            }
+            3 => self.pressed_keys[self.selected_key as usize],
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn test_vip() {
+    #[rustfmt::skip]
+    let rom = [
+        // 8000: continue at 0x8008 with R2 as the program counter, which moves the ROM out of the way
+        0xF8, 0x80, 0xB2, 0xF8, 0x08, 0xA2, 0xD2, 0x00,
+        // 8008: continue in RAM at 0 with R0
+        0xF8, 0x00, 0xA0, 0xB0, 0xD0, 0x00, 0x00, 0x00,
+        // 8010: return from the interrupt, leaving R1 at the entry
+        0x70,
+        // 8011: save X and P, point R0 at the display, and wait for it to start
+        0x22, 0x78, 0xF8, 0x02, 0xB0, 0xF8, 0x00, 0xA0, 0x34, 0x19, 0x30, 0x10,
+    ];
+    #[rustfmt::skip]
+    let interpreter = [
+        0xF8, 0x80, 0xB1, 0xF8, 0x11, 0xA1, // R1 = 0x8011, the interrupt routine
+        0xF8, 0x0E, 0xB2, 0xF8, 0xFF, 0xA2, // R2 = 0x0EFF, the stack
+        0xE2, 0x69, // turn on the display
+        0xF8, 0x00, 0xB3, 0xF8, 0x1F, 0xA3, // R3 = 0x001F
+        0xE3, 0x62, // select key 5
+        0x3E, 0x1C, // 0016: skip SEQ unless key 5 is down
+        0x7B, 0x30, 0x16, 0x00, // SEQ, loop
+        0x7A, 0x30, 0x16, // 001C: REQ, loop
+        0x05,
+    ];
+    // each line shows its number
+    let program: [u8; DISPLAY_LINES * 8] =
+        core::array::from_fn(|i| (i % 8 == 0) as u8 * (i / 8) as u8);
+    let image = VipImage {
+        rom: &rom,
+        interpreter: &interpreter,
+        program: &program,
+    };
+    let mut vip = Vip::load(&image).unwrap();
+    for _ in 0..3 {
+        let summary = vip.run_for(TIMER_PERIOD, [false; 16]).unwrap();
+        assert_eq!(summary.timer_ticks, 1);
+    }
+    // the second row is the third line, where bit 1 of the first byte is set
+    let row: [u8; 16] = core::array::from_fn(|x| vip.display.get(x, 1));
+    assert_eq!(row, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0]);
+    // and the last row is line 126
+    let row: [u8; 16] = core::array::from_fn(|x| vip.display.get(x, 63));
+    assert_eq!(row, [0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0]);
+    assert!(!vip.is_sound_on());
+
+    let mut keys = [false; 16];
+    keys[5] = true;
+    let summary = vip.run_for(TIMER_PERIOD, keys).unwrap();
+    assert!(vip.is_sound_on());
+    assert_eq!(summary.display, DisplayState::NotUpdated);
+}
@@ -0,0 +1,172 @@
+//! Runs programs headlessly for a fixed number of frames with scripted key presses
+//! and compares the final display to the golden images in `tests/golden`.
+//!
+//! The test programs in `tests/roms` are Octo sources, assembled before each run.
+//! Set `CHIPRS_BLESS=1` to write the current display over a golden image after checking it by hand.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use chiprs::{assembler, Chip8, Display, Quirks, TIMER_PERIOD};
+
+/// 10 instructions per frame
+const INSTRUCTIONS_PER_SECOND: u32 = 600;
+
+/// A key held down during a range of frames
+struct KeyPress {
+    key: usize,
+    frames: Range<u32>,
+}
+
+struct Run<'a> {
+    /// the name of the golden image
+    name: &'a str,
+    program: Vec<u8>,
+    quirks: Quirks,
+    frames: u32,
+    keys: &'a [KeyPress],
+}
+
+impl Run<'_> {
+    fn display(&self) -> Display {
+        let mut emulator = Chip8::load_program(&self.program, self.quirks)
+            .unwrap()
+            .with_seed(0)
+            .with_speed(INSTRUCTIONS_PER_SECOND);
+        for frame in 0..self.frames {
+            let mut pressed_keys = [false; 16];
+            for press in self.keys {
+                if press.frames.contains(&frame) {
+                    pressed_keys[press.key] = true;
+                }
+            }
+            emulator
+                .run_for(TIMER_PERIOD, pressed_keys)
+                .unwrap_or_else(|err| panic!("{}: frame {frame}: {err}", self.name));
+        }
+        emulator.display
+    }
+
+    fn check(&self) {
+        let actual = render(&self.display());
+        let path = golden_dir().join(format!("{}.txt", self.name));
+        if std::env::var_os("CHIPRS_BLESS").is_some() {
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+            panic!(
+                "{}: {err}, run with CHIPRS_BLESS=1 to create it from this display:\n{actual}",
+                path.display()
+            )
+        });
+        assert!(
+            actual == expected,
+            "{} doesn't match {}\nexpected:\n{expected}\nactual:\n{actual}",
+            self.name,
+            path.display()
+        );
+    }
+}
+
+/// One line per row, `.` for unset pixels and the color index for the rest
+fn render(display: &Display) -> String {
+    let mut text = String::new();
+    for row in display.rows() {
+        for &color in row {
+            text.push(match color {
+                0 => '.',
+                color => char::from_digit(color as u32, 10).unwrap(),
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn rom(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/roms")
+        .join(name);
+    let source = fs::read_to_string(&path).unwrap();
+    assembler::assemble(&source).unwrap_or_else(|err| panic!("{}:{err}", path.display()))
+}
+
+#[test]
+fn test_ibm_logo() {
+    Run {
+        name: "ibm_logo",
+        program: include_bytes!("../programs/IBM Logo.ch8").to_vec(),
+        quirks: Quirks::chip48(),
+        frames: 20,
+        keys: &[],
+    }
+    .check();
+}
+
+#[test]
+fn test_flags() {
+    // the results don't depend on the quirks
+    for quirks in [Quirks::vip(), Quirks::xo_chip()] {
+        Run {
+            name: "flags",
+            program: rom("flags.8o"),
+            quirks,
+            frames: 60,
+            keys: &[],
+        }
+        .check();
+    }
+}
+
+#[test]
+fn test_quirks() {
+    for (name, quirks) in [
+        ("quirks_vip", Quirks::vip()),
+        ("quirks_chip48", Quirks::chip48()),
+        ("quirks_xo_chip", Quirks::xo_chip()),
+    ] {
+        Run {
+            name,
+            program: rom("quirks.8o"),
+            quirks,
+            frames: 30,
+            keys: &[],
+        }
+        .check();
+    }
+}
+
+#[test]
+fn test_keypad() {
+    Run {
+        name: "keypad",
+        program: rom("keypad.8o"),
+        quirks: Quirks::chip48(),
+        frames: 40,
+        keys: &[
+            KeyPress {
+                key: 0x1,
+                frames: 5..8,
+            },
+            KeyPress {
+                key: 0xA,
+                frames: 12..15,
+            },
+            KeyPress {
+                key: 0xF,
+                frames: 20..23,
+            },
+            KeyPress {
+                key: 0x5,
+                frames: 30..33,
+            },
+        ],
+    }
+    .check();
+}
@@ -0,0 +1,364 @@
+//! Differential tests that run the same programs with every [`Engine`]
+//! and check that they stay in the same state as the interpreter after every instruction.
+
+use std::fs;
+use std::path::Path;
+
+use chiprs::{assembler, Chip8, Engine, ProgramImage, Quirks, Timing, TIMER_PERIOD};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const ENGINES: [Engine; 2] = [Engine::CachedDecode, Engine::Recompiler];
+
+fn registers(emulator: &Chip8) -> (u16, u16, [u8; 16], Vec<u16>) {
+    (
+        emulator.pc(),
+        emulator.index(),
+        *emulator.registers(),
+        emulator.stack().to_vec(),
+    )
+}
+
+/// Runs `program` for `steps` instructions with the keys pressed on each step given by `keys`
+fn compare(program: &[u8], quirks: Quirks, steps: u32, mut keys: impl FnMut(u32) -> [bool; 16]) {
+    let load = |engine| {
+        Chip8::load_program(program, quirks)
+            .unwrap()
+            .with_seed(0)
+            .with_engine(engine)
+    };
+    let mut reference = load(Engine::Interpreter);
+    let mut others: Vec<Chip8> = ENGINES.into_iter().map(load).collect();
+    for step in 0..steps {
+        let pressed_keys = keys(step);
+        let pc = reference.pc();
+        let expected = reference.step(pressed_keys);
+        for emulator in &mut others {
+            let engine = emulator.engine();
+            assert_eq!(
+                emulator.step(pressed_keys),
+                expected,
+                "{engine:?}: step {step} at {pc:#06x}"
+            );
+            assert_eq!(
+                registers(emulator),
+                registers(&reference),
+                "{engine:?}: step {step} at {pc:#06x}"
+            );
+            // the full state is large, so only compare it now and then
+            if step % 256 == 0 {
+                assert!(
+                    emulator.save_state() == reference.save_state(),
+                    "{engine:?}: step {step} at {pc:#06x}"
+                );
+            }
+        }
+        // carry on past the error, e.g. a font character out of range, to cover more code
+        if expected.is_err() {
+            for emulator in others.iter_mut().chain([&mut reference]) {
+                emulator.set_pc(pc.wrapping_add(2));
+            }
+        }
+        if step % 10 == 9 {
+            reference.tick_timers();
+            others.iter_mut().for_each(Chip8::tick_timers);
+        }
+    }
+    for emulator in &others {
+        assert!(emulator.save_state() == reference.save_state());
+    }
+}
+
+/// Like [`compare`], but runs `frames` frames with [`Chip8::run_for`], which lets the recompiler run a block at a time
+fn compare_frames(
+    program: &[u8],
+    quirks: Quirks,
+    frames: u32,
+    mut keys: impl FnMut(u32) -> [bool; 16],
+) {
+    for timing in [Timing::Instructions, Timing::Vip] {
+        let load = |engine| {
+            Chip8::load_program(program, quirks)
+                .unwrap()
+                .with_seed(0)
+                .with_timing(timing)
+                .with_engine(engine)
+        };
+        let mut reference = load(Engine::Interpreter);
+        let mut others: Vec<Chip8> = ENGINES.into_iter().map(load).collect();
+        for frame in 0..frames {
+            let pressed_keys = keys(frame);
+            let expected = reference.run_for(TIMER_PERIOD, pressed_keys);
+            for emulator in &mut others {
+                let engine = emulator.engine();
+                assert_eq!(
+                    emulator.run_for(TIMER_PERIOD, pressed_keys),
+                    expected,
+                    "{engine:?}, {timing:?}: frame {frame}"
+                );
+                assert!(
+                    emulator.save_state() == reference.save_state(),
+                    "{engine:?}, {timing:?}: frame {frame}"
+                );
+            }
+            // carry on past the error in the next frame
+            if expected.is_err() {
+                let pc = reference.pc().wrapping_add(2);
+                for emulator in others.iter_mut().chain([&mut reference]) {
+                    emulator.set_pc(pc);
+                }
+            }
+        }
+    }
+}
+
+fn rom(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/roms")
+        .join(name);
+    assembler::assemble(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_programs() {
+    compare(
+        include_bytes!("../programs/IBM Logo.ch8"),
+        Quirks::chip48(),
+        1000,
+        |_| [false; 16],
+    );
+    // move and fire now and then
+    compare(
+        include_bytes!("../programs/Space Invaders [David Winter].ch8"),
+        Quirks::vip(),
+        50_000,
+        |step| {
+            let mut keys = [false; 16];
+            keys[[4, 5, 6][(step / 500) as usize % 3]] = step % 1000 < 300;
+            keys
+        },
+    );
+    for quirks in [Quirks::vip(), Quirks::chip48(), Quirks::xo_chip()] {
+        for name in ["flags.8o", "quirks.8o", "keypad.8o"] {
+            compare(&rom(name), quirks, 2000, |step| {
+                let mut keys = [false; 16];
+                keys[(step / 100) as usize % 16] = step % 100 < 50;
+                keys
+            });
+        }
+    }
+}
+
+#[test]
+fn test_run_for() {
+    // move and fire now and then
+    compare_frames(
+        include_bytes!("../programs/Space Invaders [David Winter].ch8"),
+        Quirks::vip(),
+        1000,
+        |frame| {
+            let mut keys = [false; 16];
+            keys[[4, 5, 6][(frame / 30) as usize % 3]] = frame % 60 < 20;
+            keys
+        },
+    );
+    for quirks in [Quirks::vip(), Quirks::chip48(), Quirks::xo_chip()] {
+        for name in ["flags.8o", "quirks.8o"] {
+            compare_frames(&rom(name), quirks, 100, |_| [false; 16]);
+        }
+    }
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..50 {
+        let program = random_program(&mut rng);
+        let quirks = [Quirks::vip(), Quirks::chip48(), Quirks::xo_chip()][rng.gen_range(0..3)];
+        let keys: u16 = rng.gen();
+        compare_frames(&program, quirks, 30, |frame| {
+            let mut pressed_keys = [false; 16];
+            for (key, pressed) in pressed_keys.iter_mut().enumerate() {
+                *pressed = (keys.rotate_left(frame) >> key) & 1 == 1;
+            }
+            pressed_keys
+        });
+    }
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let program = [
+        0xA2, 0x10, // I = 0x210
+        0x22, 0x10, // call 0x210
+        0x60, 0x6B, // V0 = 0x6B
+        0x61, 0x05, // V1 = 0x05
+        0xF1, 0x55, // store V0 and V1 at 0x210, replacing VA = 1 with VB = 5
+        0x22, 0x10, // call 0x210 again
+        0x12, 0x0C, // loop forever
+        0x00, 0x00, //
+        0x6A, 0x01, // VA = 1
+        0x00, 0xEE, // return
+    ];
+    for engine in ENGINES {
+        let mut emulator = Chip8::load_program(&program, Quirks::default())
+            .unwrap()
+            .with_engine(engine);
+        for _ in 0..12 {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.registers()[0xA], 1, "{engine:?}");
+        assert_eq!(emulator.registers()[0xB], 5, "{engine:?}");
+
+        // writes from outside the program count too
+        emulator.poke(0x210, 0x6C);
+        emulator.set_pc(0x20A);
+        for _ in 0..3 {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.registers()[0xC], 5, "{engine:?}");
+    }
+    compare(&program, Quirks::default(), 100, |_| [false; 16]);
+
+    // the overwritten instruction follows the store without a jump in between
+    let program = [
+        0x60, 0x6D, // V0 = 0x6D
+        0x61, 0x07, // V1 = 0x07
+        0xA2, 0x0A, // I = 0x20A
+        0xF1, 0x55, // store V0 and V1 at 0x20A, replacing VC = 1 with VD = 7
+        0x6E, 0x01, // VE = 1
+        0x6C, 0x01, // VC = 1
+        0x12, 0x0C, // loop forever
+    ];
+    for engine in ENGINES {
+        let mut emulator = Chip8::load_program(&program, Quirks::default())
+            .unwrap()
+            .with_engine(engine);
+        for _ in 0..7 {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.registers()[0xC], 0, "{engine:?}");
+        assert_eq!(emulator.registers()[0xD], 7, "{engine:?}");
+    }
+    // run_for executes the block in one go, which has to notice the store too
+    compare_frames(&program, Quirks::default(), 3, |_| [false; 16]);
+
+    // the store is the last instruction in memory, which the recompiler leaves to the interpreter
+    let program = [
+        0xA2, 0x08, // I = 0x208
+        0x60, 0x6D, // V0 = 0x6D
+        0x61, 0x07, // V1 = 0x07
+        0x12, 0x08, // jump 0x208
+        0x7C, 0x01, // VC += 1
+        0x12, 0x0A, // loop forever
+    ];
+    let image = ProgramImage {
+        start: 0,
+        segments: &[
+            (0x200, &program),
+            // store V0 and V1 at 0x208, replacing VC += 1 with VD = 7
+            (0xFFFE, &[0xF1, 0x55]),
+            // jump 0x208
+            (0x0000, &[0x12, 0x08]),
+        ],
+        ..Default::default()
+    };
+    for engine in ENGINES {
+        let mut emulator = Chip8::load_image(&image, Quirks::default())
+            .unwrap()
+            .with_engine(engine);
+        for _ in 0..6 {
+            emulator.step([false; 16]).unwrap();
+        }
+        // no jump reaches that far
+        emulator.set_pc(0xFFFE);
+        for _ in 0..3 {
+            emulator.step([false; 16]).unwrap();
+        }
+        assert_eq!(emulator.registers()[0xC], 1, "{engine:?}");
+        assert_eq!(emulator.registers()[0xD], 7, "{engine:?}");
+    }
+}
+
+/// Opcodes with the bits that are filled in at random, for every instruction except 00FD exit.
+/// The 0NNN and 9XYN patterns also produce opcodes that only the interpreter handles.
+const TEMPLATES: &[(u16, u16)] = &[
+    (0x0000, 0x0FFF),
+    (0x00C0, 0x000F),
+    (0x00D0, 0x000F),
+    (0x00E0, 0x0000),
+    (0x00EE, 0x0000),
+    (0x00FB, 0x0000),
+    (0x00FC, 0x0000),
+    (0x00FE, 0x0000),
+    (0x00FF, 0x0000),
+    (0x1000, 0x0FFF),
+    (0x2000, 0x0FFF),
+    (0x3000, 0x0FFF),
+    (0x4000, 0x0FFF),
+    (0x5000, 0x0FF0),
+    (0x5002, 0x0FF0),
+    (0x5003, 0x0FF0),
+    (0x6000, 0x0FFF),
+    (0x7000, 0x0FFF),
+    (0x8000, 0x0FF0),
+    (0x8001, 0x0FF0),
+    (0x8002, 0x0FF0),
+    (0x8003, 0x0FF0),
+    (0x8004, 0x0FF0),
+    (0x8005, 0x0FF0),
+    (0x8006, 0x0FF0),
+    (0x8007, 0x0FF0),
+    (0x800E, 0x0FF0),
+    (0x9000, 0x0FFF),
+    (0xA000, 0x0FFF),
+    (0xB000, 0x0FFF),
+    (0xC000, 0x0FFF),
+    (0xD000, 0x0FFF),
+    (0xE09E, 0x0F00),
+    (0xE0A1, 0x0F00),
+    (0xF000, 0x0000),
+    (0xF001, 0x0F00),
+    (0xF002, 0x0000),
+    (0xF007, 0x0F00),
+    (0xF00A, 0x0F00),
+    (0xF015, 0x0F00),
+    (0xF018, 0x0F00),
+    (0xF01E, 0x0F00),
+    (0xF029, 0x0F00),
+    (0xF030, 0x0F00),
+    (0xF033, 0x0F00),
+    (0xF03A, 0x0F00),
+    (0xF055, 0x0F00),
+    (0xF065, 0x0F00),
+    (0xF075, 0x0F00),
+    (0xF085, 0x0F00),
+];
+
+/// Random instructions whose addresses point into the program, so that it jumps around
+/// and overwrites itself with FX33, FX55 and 5XY2
+fn random_program(rng: &mut StdRng) -> Vec<u8> {
+    (0..128)
+        .flat_map(|_| {
+            let (opcode, random_bits) = TEMPLATES[rng.gen_range(0..TEMPLATES.len())];
+            let opcode = match opcode >> 12 {
+                0x1 | 0x2 | 0xA | 0xB => opcode | rng.gen_range(0x200..0x300),
+                _ => opcode | (rng.gen::<u16>() & random_bits),
+            };
+            opcode.to_be_bytes()
+        })
+        .collect()
+}
+
+#[test]
+fn test_random_programs() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..200 {
+        let program = random_program(&mut rng);
+        let quirks = [Quirks::vip(), Quirks::chip48(), Quirks::xo_chip()][rng.gen_range(0..3)];
+        let keys: u16 = rng.gen();
+        compare(&program, quirks, 2000, |step| {
+            let mut pressed_keys = [false; 16];
+            for (key, pressed) in pressed_keys.iter_mut().enumerate() {
+                *pressed = (keys.rotate_left(step / 50) >> key) & 1 == 1;
+            }
+            pressed_keys
+        });
+    }
+}